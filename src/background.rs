@@ -0,0 +1,46 @@
+use super::parser::parse_color;
+
+// What the layers are flattened onto
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Background {
+	Color([u8; 4]),
+	Checker,
+}
+
+impl Background {
+	// Returns the Background with the given name or color, if there is one.
+	// Takes any color a directive would (#f008, lr, .), transparent or checker.
+	pub fn from_name(s: &str) -> Option<Background> {
+		match s.trim() {
+			"transparent" => Some(Background::Color([255, 255, 255, 0])),
+			"checker" | "checkerboard" => Some(Background::Checker),
+			s => parse_color(s).map(Background::Color),
+		}
+	}
+
+	// Returns whether the background shows through where nothing is drawn
+	pub fn transparent(&self) -> bool {
+		match self {
+			Background::Color(c) => c[3] < 255,
+			Background::Checker => false,
+		}
+	}
+
+	// Returns the background as an RGBA buffer
+	pub fn fill(&self, width: usize, height: usize) -> Vec<u8> {
+		match self {
+			Background::Color(c) => c.repeat(width * height),
+			Background::Checker => {
+				// The usual see-through preview, in squares of 4 pyxels
+				let mut v = Vec::with_capacity(width * height * 4);
+				for y in 0..height {
+					for x in 0..width {
+						let shade = if (x / 4 + y / 4) % 2 == 0 { 255 } else { 204 };
+						v.extend_from_slice(&[shade, shade, shade, 255]);
+					}
+				}
+				v
+			}
+		}
+	}
+}
@@ -0,0 +1,162 @@
+use super::cmd::Span;
+use super::pyxel::*;
+
+// The pyxels drawn at one pixel, with the names of their layers
+type Drawn<'a> = Vec<(&'a str, &'a Pyxel)>;
+
+// Returns every pyxel drawn at (x, y) of the output image, bottom layer first and in the order drawn,
+// with the name of its layer. None if (x, y) is outside the image.
+pub fn pyxels_at(pyxels: &PyxelVec, x: usize, y: usize) -> Option<Drawn<'_>> {
+	let (ax, ay, width, height) = pyxels.area();
+	if x >= width || y >= height {
+		return None;
+	}
+	let (cx, cy) = (ax + x as isize, ay + y as isize);
+	let mut v = vec![];
+	for layer in pyxels.layers() {
+		for p in layer.pyxels().iter().filter(|p| p.x() == cx && p.y() == cy) {
+			v.push((layer.name(), p));
+		}
+	}
+	Some(v)
+}
+
+// Returns what pyxels_at does for every pixel of the output image at once, indexed by y * width + x
+pub fn pyxels_by_position(pyxels: &PyxelVec) -> Vec<Drawn<'_>> {
+	let (ax, ay, width, height) = pyxels.area();
+	let mut v = vec![vec![]; width * height];
+	for layer in pyxels.layers() {
+		for p in layer.pyxels().iter() {
+			let (x, y) = (p.x() - ax, p.y() - ay);
+			if x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height {
+				v[y as usize * width + x as usize].push((layer.name(), p));
+			}
+		}
+	}
+	v
+}
+
+// Prints which commands drew at (x, y) of the output image, last one first
+pub fn print(pyxels: &PyxelVec, source: &str, x: usize, y: usize) {
+	let (_, _, width, height) = pyxels.area();
+	match pyxels_at(pyxels, x, y) {
+		None => println!("toimg: ({}, {}) is outside the {}x{} image", x, y, width, height),
+		Some(v) if v.is_empty() => println!("toimg: Nothing was drawn at ({}, {})", x, y),
+		Some(v) => {
+			for (layer, p) in v.iter().rev() {
+				println!("{} {} {} {}", p.span(), layer, describe(p), text(source, p.span()));
+			}
+		}
+	}
+}
+
+// Returns the pyxels drawn at (x, y) of the output image, or at every position if there is none, as JSON:
+// {"width": w, "height": h, "pixels": [{"x": x, "y": y, "drawn": [{"layer": name, "line": n, ...}, ...]}, ...]}.
+// Pixels are listed row by row and what was drawn at each bottom layer first.
+pub fn json(pyxels: &PyxelVec, source: &str, at: Option<(usize, usize)>) -> String {
+	let (_, _, width, height) = pyxels.area();
+	// Each pixel drawn on, or just the one asked for
+	let drawn: Vec<((usize, usize), Drawn)> = match at {
+		Some((x, y)) => pyxels_at(pyxels, x, y).map(|v| ((x, y), v)).into_iter().collect(),
+		None => pyxels_by_position(pyxels)
+			.into_iter()
+			.enumerate()
+			.filter(|(_, v)| !v.is_empty())
+			.map(|(i, v)| ((i % width, i / width), v))
+			.collect(),
+	};
+	let mut pixels = vec![];
+	for ((x, y), drawn) in drawn {
+		let drawn: Vec<String> = drawn
+			.iter()
+			.map(|(layer, p)| {
+				let span = p.span();
+				format!(
+					"{{\"layer\": {}, \"line\": {}, \"column\": {}, \"end_line\": {}, \"end_column\": {}, \"text\": {}, \"color\": \"{}\"}}",
+					string(layer),
+					span.line(),
+					span.column(),
+					span.end_line(),
+					span.end_column(),
+					string(&text(source, span)),
+					describe(p),
+				)
+			})
+			.collect();
+		pixels.push(format!("{{\"x\": {}, \"y\": {}, \"drawn\": [{}]}}", x, y, drawn.join(", ")));
+	}
+	format!("{{\"width\": {}, \"height\": {}, \"pixels\": [{}]}}", width, height, pixels.join(", "))
+}
+
+// Returns the color a pyxel was drawn with as #rrggbbaa, or erase followed by how much it cleared
+fn describe(p: &Pyxel) -> String {
+	if p.erase() {
+		format!("erase {}", p.a())
+	} else {
+		format!("#{:02x}{:02x}{:02x}{:02x}", p.r(), p.g(), p.b(), p.a())
+	}
+}
+
+// Returns what is written at a span of the source, on one line
+fn text(source: &str, span: Span) -> String {
+	// Pyxels drawn by commands not written in the source have no span
+	if span.line() == 0 || span.end_line() < span.line() {
+		return String::new();
+	}
+	let lines: Vec<&str> = source.lines().skip(span.line() - 1).take(span.end_line() - span.line() + 1).collect();
+	let mut s = String::new();
+	for (i, line) in lines.iter().enumerate() {
+		let from = if i == 0 { span.column().saturating_sub(1) } else { 0 };
+		let to = if i + 1 == lines.len() { span.end_column() } else { line.chars().count() };
+		let part: String = line.chars().skip(from).take(to.saturating_sub(from)).collect();
+		if !s.is_empty() {
+			s.push(' ');
+		}
+		s.push_str(part.trim());
+	}
+	s
+}
+
+// Returns s as a JSON string, quoted and escaped
+fn string(s: &str) -> String {
+	let mut v = String::from("\"");
+	for c in s.chars() {
+		match c {
+			'"' => v.push_str("\\\""),
+			'\\' => v.push_str("\\\\"),
+			'\n' => v.push_str("\\n"),
+			'\t' => v.push_str("\\t"),
+			c if (c as u32) < 0x20 => v.push_str(&format!("\\u{:04x}", c as u32)),
+			c => v.push(c),
+		}
+	}
+	v.push('"');
+	v
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn grouped_like_pyxels_at() {
+		let mut pyxels = PyxelVec::new(3, 2);
+		pyxels.push(Pyxel::new([255, 0, 0, 255], 1, 1));
+		pyxels.push(Pyxel::new([0, 255, 0, 255], 2, 0));
+		pyxels.select_layer("top");
+		pyxels.push(Pyxel::new([0, 0, 255, 255], 1, 1));
+		let grouped = pyxels_by_position(&pyxels);
+		assert_eq!(grouped.len(), 6);
+		for y in 0..2 {
+			for x in 0..3 {
+				let at = pyxels_at(&pyxels, x, y).unwrap();
+				let group = &grouped[y * 3 + x];
+				assert_eq!(at.len(), group.len());
+				for ((la, pa), (lb, pb)) in at.iter().zip(group.iter()) {
+					assert_eq!((la, pa.color_f32()), (lb, pb.color_f32()));
+				}
+			}
+		}
+		assert_eq!(grouped[4].iter().map(|(l, _)| *l).collect::<Vec<&str>>(), vec!["base", "top"]);
+	}
+}
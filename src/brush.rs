@@ -0,0 +1,88 @@
+// The shape painted around every position of a stroke
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Brush {
+	Square(usize),
+	Round(usize),
+	Mask(Vec<(isize, isize)>),
+}
+
+impl Brush {
+	// Builds a Brush from directive arguments, e.g. "round", "5" or "mask", ".x./xxx/.x."
+	pub fn from_args(shape: &str, arg: &str, size: usize) -> Brush {
+		match shape {
+			"round" => Brush::Round(size.max(1)),
+			"mask" => {
+				// Rows are separated by slashes. Anything but a dot, space or 0 is painted.
+				let rows: Vec<&str> = arg.split('/').collect();
+				let h = rows.len() as isize;
+				let w = rows.iter().map(|r| r.chars().count()).max().unwrap_or(0) as isize;
+				let mut offsets = vec![];
+				for (y, row) in rows.iter().enumerate() {
+					for (x, c) in row.chars().enumerate() {
+						if c != '.' && c != ' ' && c != '0' {
+							offsets.push((x as isize - (w - 1) / 2, y as isize - (h - 1) / 2));
+						}
+					}
+				}
+				Brush::Mask(offsets)
+			}
+			_ => Brush::Square(size.max(1)),
+		}
+	}
+
+	// Returns the positions the brush covers, relative to where it is put.
+	// Odd sizes are centred, even sizes lean to the bottom right.
+	pub fn offsets(&self) -> Vec<(isize, isize)> {
+		match self {
+			Brush::Square(n) => {
+				let n = *n as isize;
+				let mut v = vec![];
+				for y in 0..n {
+					for x in 0..n {
+						v.push((x - (n - 1) / 2, y - (n - 1) / 2));
+					}
+				}
+				v
+			}
+			Brush::Round(n) => {
+				let c = (*n as f64 - 1.0) / 2.0;
+				let r = *n as f64 / 2.0 - 0.25;
+				let n = *n as isize;
+				let mut v = vec![];
+				for y in 0..n {
+					for x in 0..n {
+						let (dx, dy) = (x as f64 - c, y as f64 - c);
+						if dx * dx + dy * dy <= r * r {
+							v.push((x - (n - 1) / 2, y - (n - 1) / 2));
+						}
+					}
+				}
+				v
+			}
+			Brush::Mask(offsets) => offsets.clone(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn masks_leave_out_dots_spaces_and_zeros() {
+		let brush = Brush::from_args("mask", ".x./x 0/0x.", 1);
+		assert_eq!(brush.offsets(), [(0, -1), (-1, 0), (0, 1)]);
+	}
+
+	#[test]
+	fn squares_lean_to_the_bottom_right() {
+		assert_eq!(Brush::from_args("square", "", 2).offsets(), [(0, 0), (1, 0), (0, 1), (1, 1)]);
+		assert_eq!(Brush::from_args("square", "", 3).offsets().len(), 9);
+	}
+
+	#[test]
+	fn round_brushes_cut_the_corners() {
+		let offsets = Brush::from_args("round", "", 3).offsets();
+		assert_eq!(offsets, [(0, -1), (-1, 0), (0, 0), (1, 0), (0, 1)]);
+	}
+}
@@ -1,5 +1,78 @@
+use super::brush::*;
+use super::layer::*;
+use super::limits::*;
+use super::lsystem::*;
+use super::mask::*;
+use super::noise::*;
+use super::eval::*;
+use super::parser::{parse_color, parse_commands};
+use super::pen::*;
 use super::pointer::*;
 use super::pyxel::*;
+use super::raster::*;
+use super::settings::*;
+use super::shader::*;
+use super::stamp::*;
+use super::symmetry::*;
+use super::transform::*;
+use super::traversal::*;
+use image::RgbaImage;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+
+// Where a command was written in the source, from its first character to its last.
+// Lines and columns are counted from 1.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Span {
+	line: usize,
+	column: usize,
+	end_line: usize,
+	end_column: usize,
+}
+
+impl Span {
+	// Returns a new Span. Takes where it starts and where it ends.
+	pub fn new(line: usize, column: usize, end_line: usize, end_column: usize) -> Self {
+		Span {
+			line,
+			column,
+			end_line,
+			end_column,
+		}
+	}
+
+	// Returns respective values
+	pub fn line(&self) -> usize {
+		self.line
+	}
+
+	pub fn column(&self) -> usize {
+		self.column
+	}
+
+	pub fn end_line(&self) -> usize {
+		self.end_line
+	}
+
+	pub fn end_column(&self) -> usize {
+		self.end_column
+	}
+}
+
+// Formats as line:column, with the end after a - if it is somewhere else (ex: 3:5-9 or 3:5-4:2)
+impl fmt::Display for Span {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}", self.line(), self.column())?;
+		if self.end_line() != self.line() {
+			write!(f, "-{}:{}", self.end_line(), self.end_column())
+		} else if self.end_column() != self.column() {
+			write!(f, "-{}", self.end_column())
+		} else {
+			Ok(())
+		}
+	}
+}
 
 // The Command struct
 #[derive(Debug, Clone)]
@@ -9,6 +82,10 @@ pub struct Command {
 	int: isize,
 	rep: usize,
 	unset: bool,
+	name: String,
+	args: Vec<String>,
+	span: Span,
+	text: String,
 }
 
 impl Command {
@@ -20,6 +97,25 @@ impl Command {
 			int,
 			rep,
 			unset,
+			name: String::new(),
+			args: vec![],
+			span: Span::default(),
+			text: String::new(),
+		}
+	}
+
+	// Returns a new directive Command, written in the source as @name(args)
+	pub fn directive(name: String, args: Vec<String>, int: isize, rep: usize, unset: bool) -> Self {
+		Command {
+			verb: '@',
+			hex: [255; 4],
+			int,
+			rep,
+			unset,
+			name,
+			args,
+			span: Span::default(),
+			text: String::new(),
 		}
 	}
 
@@ -47,6 +143,48 @@ impl Command {
 	pub fn unset(&self) -> bool {
 		self.unset
 	}
+
+	// Returns name. The name of a directive.
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	// Returns args. The raw comma separated arguments of a directive.
+	pub fn args(&self) -> &[String] {
+		&self.args
+	}
+
+	// Returns the argument at i as text, or an empty string if there is none
+	pub fn arg(&self, i: usize) -> &str {
+		match self.args.get(i) {
+			Some(a) => a,
+			None => "",
+		}
+	}
+
+	// Returns span. Where the command was written.
+	pub fn span(&self) -> Span {
+		self.span
+	}
+
+	// Returns text. The command as it was written, or an empty string for those not written in the source.
+	pub fn text(&self) -> &str {
+		&self.text
+	}
+
+	pub fn set_span(&mut self, span: Span) {
+		self.span = span;
+	}
+
+	pub fn set_text(&mut self, text: String) {
+		self.text = text;
+	}
+
+	// Evaluates up to count arguments from `from` onwards, fewer if there aren't that many.
+	// Prints the first that is not a number and returns None.
+	pub fn numbers(&self, from: usize, count: usize, eval: &Eval) -> Option<Vec<f64>> {
+		eval.numbers(self.name(), self.args.iter().skip(from).take(count).map(|a| a.as_str()))
+	}
 }
 
 // My sort of iterator
@@ -73,121 +211,1012 @@ impl Looper {
 	}
 }
 
-// Run the commands and modify the Tab's Pyxels
-pub fn run(pointer: &mut Pointer, comms: Vec<Command>) -> PyxelVec {
-	let mut pyxels: PyxelVec = PyxelVec::new(pointer.width(), pointer.height());
+// Everything the commands change besides the pointer
+struct State {
+	pyxels: PyxelVec,
+	pen: Pen,
+	images: HashMap<PathBuf, Option<RgbaImage>>,
+	eval: Eval,
+	saved: Vec<Pointer>,
+	marks: HashMap<(String, String), (isize, isize)>,
+	pointers: Vec<Pointer>,
+	selected: Vec<String>,
+	// Indices of the directives whose expansions are running, outermost first
+	path: Vec<usize>,
+	budget: Budget,
+	error: Option<LimitError>,
+}
+
+impl State {
+	// Counts n steps of work against the limits. Returns false, keeping the error, once one is passed.
+	fn charge(&mut self, n: usize) -> bool {
+		if self.error.is_none() {
+			self.error = self.budget.charge(n).err();
+		}
+		self.error.is_none()
+	}
+}
+
+// Run the commands and modify the Tab's Pyxels.
+// Stops with an error as soon as the commands go past one of the settings' limits.
+pub fn run(pointer: &mut Pointer, comms: Vec<Command>, settings: &Settings) -> Result<PyxelVec, LimitError> {
+	let mut state = State {
+		pyxels: PyxelVec::new(pointer.width(), pointer.height()),
+		pen: Pen::new(),
+		images: HashMap::new(),
+		eval: Eval::new(settings.seed()),
+		saved: vec![],
+		marks: HashMap::new(),
+		pointers: vec![],
+		selected: vec![pointer.name().to_string()],
+		path: vec![],
+		budget: Budget::new(*settings.limits()),
+		error: None,
+	};
+	let (infinite_x, infinite_y) = settings.infinite();
+	if infinite_x || infinite_y {
+		state.pyxels.set_infinite(infinite_x, infinite_y);
+		state.pyxels.set_padding(settings.padding());
+		pointer.set_edges(
+			if infinite_x { Edge::Grow } else { Edge::Wrap },
+			if infinite_y { Edge::Grow } else { Edge::Torus },
+		);
+	}
+	exec(pointer, &comms, settings, &mut state);
+	if let Some(e) = state.error {
+		return Err(e);
+	}
+	let (_, _, width, height) = state.pyxels.area();
+	settings.limits().check_pixels(width, height)?;
+	Ok(state.pyxels)
+}
+
+// Runs a list of commands. Directives that expand into more commands run them through here too.
+fn exec(pointer: &mut Pointer, comms: &[Command], settings: &Settings, state: &mut State) {
 	let mut i = 0;
 	let mut repeat_table: Vec<Looper> = vec![];
-	
+
 	while i < comms.len() {
-		pointer.check_pos();
 		let cmd = &comms[i];
+		let at = i;
 		match cmd.verb() {
-			'^' => {
+			'[' => {
+				if !state.charge(1) {
+					return;
+				}
+				repeat_table.push(Looper {
+					index: i,
+					remaining: cmd.rep() - 1,
+				});
+			}
+
+			// Check for any Loopers and set i to their jump value if we have any
+			// Decrement their jump times
+			']' => {
+				if let Some(last) = repeat_table.last_mut() {
+					if last.remaining() > 0 {
+						i = last.index();
+						last.decrease();
+						if let Err(e) = state.budget.charge_loop() {
+							state.error = Some(e);
+							return;
+						}
+					} else {
+						repeat_table.pop();
+					}
+				}
+			}
+
+			_ if broadcasts(cmd) => {
+				// Every selected pointer runs it in turn. The others are kept in the state between commands.
+				for name in state.selected.clone() {
+					if name == pointer.name() {
+						traced(pointer, cmd, at, &repeat_table, settings, state);
+					} else if let Some(k) = state.pointers.iter().position(|p| p.name() == name) {
+						let mut other = state.pointers[k].clone();
+						traced(&mut other, cmd, at, &repeat_table, settings, state);
+						state.pointers[k] = other;
+					}
+				}
+			}
+
+			_ => traced(pointer, cmd, at, &repeat_table, settings, state),
+		}
+		if matches!(cmd.verb(), '[' | ']') {
+			if let Some(trace) = settings.trace().filter(|t| t.matches(&state.path, at, cmd)) {
+				trace.log(&state.path, at, cmd, pointer, pointer, &loops(&repeat_table));
+			}
+		}
+		if state.error.is_some() {
+			return;
+		}
+
+		i += 1;
+	}
+}
+
+// Runs a single command with the given pointer like apply, logging it if it is traced.
+// Each pointer a command is broadcast to pays for it.
+fn traced(pointer: &mut Pointer, cmd: &Command, i: usize, repeat_table: &[Looper], settings: &Settings, state: &mut State) {
+	if !state.charge(cost(cmd, &state.pen, pointer)) {
+		return;
+	}
+	// Anything it expands to is numbered after it
+	let trace = settings.trace().filter(|t| t.matches(&state.path, i, cmd));
+	let before = trace.map(|_| pointer.clone());
+	state.path.push(i);
+	apply(pointer, cmd, settings, state);
+	state.path.pop();
+	if let (Some(trace), Some(before)) = (trace, before) {
+		trace.log(&state.path, i, cmd, &before, pointer, &loops(repeat_table));
+	}
+}
+
+// Returns the loops being run, outermost first, as the index of their [ and how many more times they go round
+fn loops(repeat_table: &[Looper]) -> Vec<(usize, usize)> {
+	repeat_table.iter().map(|l| (l.index(), l.remaining())).collect()
+}
+
+// Runs a single command, other than a loop, with the given pointer
+fn apply(pointer: &mut Pointer, cmd: &Command, settings: &Settings, state: &mut State) {
+	pointer.check_pos();
+	state.pyxels.set_span(cmd.span());
+	match cmd.verb() {
+		'^' => {
+			if state.pen.down() {
+				let points = slide_painting(pointer, 0, -cmd.int());
+				state.pen.stroke(&mut state.pyxels, &points);
+			} else {
 				pointer.slide(0, -cmd.int());
 			}
+		}
 
-			'v' => {
+		'v' => {
+			if state.pen.down() {
+				let points = slide_painting(pointer, 0, cmd.int());
+				state.pen.stroke(&mut state.pyxels, &points);
+			} else {
 				pointer.slide(0, cmd.int());
 			}
-			
-			'<' => {
+		}
+		
+		'<' => {
+			if state.pen.down() {
+				let points = slide_painting(pointer, -cmd.int(), 0);
+				state.pen.stroke(&mut state.pyxels, &points);
+			} else {
 				pointer.slide(-cmd.int(), 0);
 			}
-			
-			'>' => {
+		}
+		
+		'>' => {
+			if state.pen.down() {
+				let points = slide_painting(pointer, cmd.int(), 0);
+				state.pen.stroke(&mut state.pyxels, &points);
+			} else {
 				pointer.slide(cmd.int(), 0);
 			}
+		}
 
-			'n' => {
-				pointer.slide(0, 1);
-				pointer.set_pos(if pointer.reverse_move_x() {
-					pointer.right()
-				} else {
-					pointer.left()
-				}, pointer.y());
+		'n' => {
+			pointer.slide(0, 1);
+			pointer.set_pos(if pointer.reverse_move_x() {
+				pointer.right()
+			} else {
+				pointer.left()
+			}, pointer.y());
+		}
+
+		's' => {
+			if cmd.unset() {
+				pointer.set_virtual_left(0);
+			} else {
+				pointer.set_virtual_left(cmd.int());
 			}
+		}
 
-			's' => {
-				if cmd.unset() {
-					pointer.set_virtual_left(0);
-				} else {
-					pointer.set_virtual_left(cmd.int());
+		'e' => {
+			if cmd.unset() {
+				pointer.set_virtual_right(pointer.width() as isize);
+			} else {
+				pointer.set_virtual_right(cmd.int());
+			}
+		}
+
+		'S' => {
+			if cmd.unset() {
+				pointer.set_virtual_top(0);
+			} else {
+				pointer.set_virtual_top(cmd.int());
+			}
+		}
+
+		'E' => {
+			if cmd.unset() {
+				pointer.set_virtual_bottom(pointer.height() as isize);
+			} else {
+				pointer.set_virtual_bottom(cmd.int());
+			}
+		}
+
+		'X' => {
+			pointer.flip_reverse_move_x();
+		}
+
+		'Y' => {
+			pointer.flop_reverse_move_y();
+		}
+
+		'#' | 'x' => {
+			let mut points = vec![];
+			let mut k = 0;
+			while k < cmd.rep() {
+				if !pointer.stopped() {
+					points.push((pointer.x(), pointer.y()));
+				}
+				pointer.advance();
+				k += 1;
+			}
+			if *cmd.verb() == 'x' {
+				state.pen.set_erasing(true);
+			} else {
+				state.pen.set_color(cmd.hex());
+			}
+			state.pen.puts(&mut state.pyxels, &points);
+		}
+
+		'@' => match cmd.name() {
+			"mirror" => {
+				let nums = match cmd.numbers(1, usize::MAX, &state.eval) {
+					Some(nums) => nums,
+					None => return,
+				};
+				let symmetry = Symmetry::from_args(cmd.arg(0), &nums, pointer.width(), pointer.height());
+				if let Symmetry::Radial(n, _, _) = symmetry {
+					if !state.charge(n) {
+						return;
+					}
 				}
+				state.pen.set_symmetry(symmetry);
 			}
 
-			'e' => {
-				if cmd.unset() {
-					pointer.set_virtual_right(pointer.width() as isize);
-				} else {
-					pointer.set_virtual_right(cmd.int());
+			"pick" => {
+				let colors: Vec<[u8; 4]> = cmd.args().iter().filter_map(|a| parse_color(a)).collect();
+				if !colors.is_empty() {
+					let mut k = 0;
+					while k < cmd.rep() {
+						let color = colors[state.eval.random().range(0, colors.len() as i64 - 1) as usize];
+						state.pen.set_color(color);
+						if !pointer.stopped() {
+							state.pen.puts(&mut state.pyxels, &[(pointer.x(), pointer.y())]);
+						}
+						pointer.advance();
+						k += 1;
+					}
 				}
 			}
 
-			'S' => {
-				if cmd.unset() {
-					pointer.set_virtual_top(0);
-				} else {
-					pointer.set_virtual_top(cmd.int());
+			"scatter" => {
+				let colors: Vec<[u8; 4]> = cmd.args().iter().skip(1).filter_map(|a| parse_color(a)).collect();
+				let count = match cmd.numbers(0, 1, &state.eval) {
+					Some(nums) => nums.first().map_or(0, |n| n.max(0.0) as usize),
+					None => return,
+				};
+				if !state.charge(count.saturating_mul(state.pen.reach())) {
+					return;
+				}
+				if !colors.is_empty() && pointer.left() < pointer.right() && pointer.top() < pointer.bottom() {
+					for _ in 0..count {
+						let mut random = state.eval.random();
+						let x = random.range(pointer.left() as i64, pointer.right() as i64 - 1) as isize;
+						let y = random.range(pointer.top() as i64, pointer.bottom() as i64 - 1) as isize;
+						let color = colors[random.range(0, colors.len() as i64 - 1) as usize];
+						drop(random);
+						state.pen.set_color(color);
+						state.pen.stroke(&mut state.pyxels, &[(x, y)]);
+					}
+				}
+			}
+
+			"noise" => {
+				if let Some(fill) = NoiseFill::from_args(cmd.args(), &state.eval, settings.seed()) {
+					match fill.pyxels(pointer.width(), pointer.height(), &mut state.budget) {
+						Ok(pyxels) => {
+							for (x, y, color) in pyxels {
+								state.pen.put(&mut state.pyxels, color, x, y);
+							}
+						}
+						Err(e) => state.error = Some(e),
+					}
+				}
+			}
+
+			"shader" => {
+				if let Some(shader) = Shader::from_args(cmd.args(), &state.eval) {
+					match shader.pyxels(&state.eval, pointer.width(), pointer.height(), settings.time(), &mut state.budget) {
+						Ok(pyxels) => {
+							for (x, y, color) in pyxels {
+								state.pen.put(&mut state.pyxels, color, x, y);
+							}
+						}
+						Err(e) => state.error = Some(e),
+					}
+				}
+			}
+
+			"fd" | "forward" | "bk" | "back" => {
+				let mut n = match cmd.numbers(0, 1, &state.eval) {
+					Some(nums) => nums.first().copied().unwrap_or(cmd.int() as f64),
+					None => return,
+				};
+				if cmd.name() == "bk" || cmd.name() == "back" {
+					n = -n;
+				}
+				if !state.charge((n.abs() as usize).saturating_mul(state.pen.reach())) {
+					return;
+				}
+				let (dx, dy) = pointer.forward(n);
+				let points = walk(pointer, dx, dy);
+				if state.pen.down() {
+					state.pen.stroke(&mut state.pyxels, &points);
+				}
+			}
+
+			"lt" | "left" | "rt" | "right" => {
+				let mut degrees = match cmd.numbers(0, 1, &state.eval) {
+					Some(nums) => match nums.first() {
+						Some(n) => *n,
+						None if cmd.unset() => 90.0,
+						None => cmd.int() as f64,
+					},
+					None => return,
+				};
+				if cmd.name() == "rt" || cmd.name() == "right" {
+					degrees = -degrees;
 				}
+				pointer.turn(degrees);
 			}
 
-			'E' => {
-				if cmd.unset() {
-					pointer.set_virtual_bottom(pointer.height() as isize);
+			"heading" => {
+				if let Some(&degrees) = cmd.numbers(0, 1, &state.eval).as_ref().and_then(|nums| nums.first()) {
+					pointer.set_heading(degrees);
+				}
+			}
+
+			"lsystem" => {
+				if let Some(mut lsystem) = LSystem::from_args(cmd.args(), &state.eval) {
+					// It can't grow past what could still be run
+					let remaining = state.budget.remaining();
+					let capped = remaining < lsystem.limit();
+					if capped {
+						lsystem.set_limit(remaining);
+					}
+					let expanded = lsystem.expand(|n| state.charge(n));
+					if state.error.is_some() {
+						return;
+					}
+					match expanded {
+						Some(symbols) if lsystem.turtle() => {
+							draw_turtle(pointer, &symbols, lsystem.angle(), lsystem.step(), state);
+						}
+						Some(symbols) => {
+							// What it expands to was written by the directive
+							let mut comms = parse_commands(&symbols);
+							for c in comms.iter_mut() {
+								c.set_span(cmd.span());
+							}
+							// Every selected pointer already runs the directive, so each runs what it expands to alone
+							let selected = std::mem::replace(&mut state.selected, vec![pointer.name().to_string()]);
+							exec(pointer, &comms, settings, state);
+							state.selected = selected;
+						}
+						None if capped => state.error = Some(LimitError::Commands(settings.limits().commands())),
+						None => state.error = Some(LimitError::Symbols(lsystem.limit())),
+					}
+				}
+			}
+
+			"push" => {
+				state.saved.push(pointer.clone());
+			}
+
+			// Each pointer only goes back to its own saves
+			"pop" => {
+				if let Some(k) = state.saved.iter().rposition(|p| p.name() == pointer.name()) {
+					*pointer = state.saved.remove(k);
+				}
+			}
+
+			"goto" => {
+				let nums = cmd.numbers(0, 2, &state.eval).unwrap_or_default();
+				if nums.len() >= 2 {
+					goto(pointer, nums[0].floor() as isize, nums[1].floor() as isize);
+				}
+			}
+
+			"mark" => {
+				state.marks.insert((pointer.name().to_string(), cmd.arg(0).to_string()), (pointer.x(), pointer.y()));
+			}
+
+			"jump" => match state.marks.get(&(pointer.name().to_string(), cmd.arg(0).to_string())) {
+				Some(&(x, y)) => goto(pointer, x, y),
+				None => println!("toimg: No mark named {}", cmd.arg(0)),
+			},
+
+			"advance" => {
+				let (r, d) = match cmd.arg(0) {
+					"right" => (1, 0),
+					"left" => (-1, 0),
+					"down" => (0, 1),
+					"up" => (0, -1),
+					"down-right" => (1, 1),
+					"down-left" => (-1, 1),
+					"up-right" => (1, -1),
+					"up-left" => (-1, -1),
+					_ => match cmd.numbers(0, 2, &state.eval) {
+						Some(nums) if nums.len() >= 2 => (nums[0] as isize, nums[1] as isize),
+						Some(_) => (1, 0),
+						None => return,
+					},
+				};
+				pointer.set_advance(r, d);
+			}
+
+			"edge" => {
+				let x = Edge::from_name(cmd.arg(0));
+				let y = Edge::from_name(cmd.arg(1)).or(x);
+				if let (Some(x), Some(y)) = (x, y) {
+					pointer.set_edges(x, y);
+				}
+			}
+
+			"traverse" => {
+				if let Some(traversal) = Traversal::from_name(cmd.arg(0)) {
+					pointer.set_traversal(traversal);
+				}
+			}
+
+			// The main pointer can't be replaced
+			"pointer" if !cmd.arg(0).is_empty() && cmd.arg(0) != pointer.name() => {
+				let mut other = pointer.clone();
+				other.set_name(cmd.arg(0));
+				let nums = match cmd.numbers(1, 2, &state.eval) {
+					Some(nums) => nums,
+					None => return,
+				};
+				if nums.len() >= 2 {
+					other.set_pos(pointer.x() + nums[0] as isize, pointer.y() + nums[1] as isize);
+				}
+				for arg in cmd.args().iter().skip(3) {
+					match arg.as_str() {
+						"X" => other.flip_reverse_move_x(),
+						"Y" => other.flop_reverse_move_y(),
+						_ => (),
+					}
+				}
+				state.pointers.retain(|p| p.name() != cmd.arg(0));
+				state.pointers.push(other);
+			}
+
+			"select" => {
+				state.selected = if cmd.arg(0) == "all" {
+					let mut all = vec![pointer.name().to_string()];
+					all.extend(state.pointers.iter().map(|p| p.name().to_string()));
+					all
+				} else if cmd.args().is_empty() {
+					vec![pointer.name().to_string()]
 				} else {
-					pointer.set_virtual_bottom(cmd.int());
+					cmd.args().to_vec()
+				};
+			}
+
+			"brush" => {
+				// A mask's second argument is its rows
+				let size = match cmd.arg(0) {
+					"mask" => 1,
+					_ => match cmd.numbers(1, 1, &state.eval) {
+						Some(nums) => nums.first().map_or(1, |n| n.max(1.0) as usize),
+						None => return,
+					},
+				};
+				if !state.charge(size.saturating_mul(size)) {
+					return;
 				}
+				state.pen.set_brush(Brush::from_args(cmd.arg(0), cmd.arg(1), size));
 			}
 
-			'X' => {
-				pointer.flip_reverse_move_x();
+			"pen" => {
+				state.pen.set_down(cmd.arg(0) == "down");
 			}
 
-			'Y' => {
-				pointer.flop_reverse_move_y();
+			"clip" => {
+				let (_, _, width, height) = state.pyxels.area();
+				if !state.charge(width * height) {
+					return;
+				}
+				state.pen.set_mask(Mask::from_args(cmd.args(), &state.eval, &state.pyxels));
 			}
 
-			'[' => {
-				repeat_table.push(Looper {
-					index: i,
-					remaining: cmd.rep() - 1,
-				});
+			"color" => {
+				if let Some(color) = parse_color(cmd.arg(0)) {
+					state.pen.set_color(color);
+				}
 			}
 
-			// Check for any Loopers and set i to their jump value if we have any
-			// Decrement their jump times
-			']' => match repeat_table.last() {
-				Some(_) => {
-					let ilen = repeat_table.len() - 1;
-					let last = &mut repeat_table[ilen];
-					if last.remaining() > 0 {
-						i = last.index();
-						last.decrease();
+			"line" | "path" => {
+				let nums = match cmd.numbers(0, usize::MAX, &state.eval) {
+					Some(nums) => nums,
+					None => return,
+				};
+				let (mut x, mut y) = (pointer.x(), pointer.y());
+				let length: f64 = nums.iter().map(|n| n.abs()).sum();
+				if !state.charge((length as usize).saturating_mul(state.pen.reach())) {
+					return;
+				}
+				let mut points = vec![];
+				for d in nums.chunks_exact(2) {
+					let (nx, ny) = (x + d[0] as isize, y + d[1] as isize);
+					if state.pen.antialias() {
+						points.extend(wu_line(x, y, nx, ny));
 					} else {
-						repeat_table.remove(ilen);
+						points.extend(line(x, y, nx, ny).into_iter().map(|(x, y)| (x, y, 1.0)));
+					}
+					x = nx;
+					y = ny;
+					if cmd.name() == "line" {
+						break;
+					}
+				}
+				state.pen.stroke_coverage(&mut state.pyxels, &points);
+				pointer.set_pos(x, y);
+			}
+
+			"ellipse" => {
+				// The radii come before fill
+				let count = cmd.args().iter().take_while(|a| *a != "fill").count();
+				let nums = match cmd.numbers(0, count, &state.eval) {
+					Some(nums) => nums,
+					None => return,
+				};
+				if let Some(rx) = nums.first() {
+					let ry = *nums.get(1).unwrap_or(rx);
+					let fill = cmd.args().iter().any(|a| a == "fill");
+					let area = if fill { (2.0 * rx + 1.0) * (2.0 * ry + 1.0) } else { 4.0 * (rx + ry) };
+					if !state.charge((area.abs() as usize).saturating_mul(state.pen.reach())) {
+						return;
+					}
+					let points = ellipse(pointer.x(), pointer.y(), *rx, ry, fill, state.pen.antialias());
+					state.pen.stroke_coverage(&mut state.pyxels, &points);
+				}
+			}
+
+			"antialias" => {
+				state.pen.set_antialias(cmd.arg(0) != "off");
+			}
+
+			"layer" => {
+				if !cmd.arg(0).is_empty() {
+					state.pyxels.select_layer(cmd.arg(0));
+				}
+				for arg in cmd.args().iter().skip(1) {
+					if let Some(blend) = Blend::from_name(arg) {
+						state.pyxels.layer_mut().set_blend(blend);
+					} else if let Some(opacity) = state.eval.eval(arg) {
+						state.pyxels.layer_mut().set_opacity(opacity as f32);
+					}
+				}
+			}
+
+			"stamp" => {
+				let path = match stamp_path(settings.dir(), cmd.arg(0)) {
+					Some(path) => path,
+					None => {
+						println!("toimg: @stamp paths must stay inside the input file's directory: {}", cmd.arg(0));
+						return;
+					}
+				};
+				if !state.images.contains_key(&path) {
+					// The header is read first so the decoded size is paid for before decoding
+					let image = match image::image_dimensions(&path) {
+						Ok((w, h)) => {
+							if !state.charge((w as usize).saturating_mul(h as usize)) {
+								return;
+							}
+							image::open(&path).map(|image| image.to_rgba())
+						}
+						Err(e) => Err(e),
+					};
+					let image = match image {
+						Ok(image) => Some(image),
+						Err(e) => {
+							println!("toimg: Could not load {}: {}", path.display(), e);
+							None
+						}
+					};
+					state.images.insert(path.clone(), image);
+				}
+				let image = &state.images[&path];
+				let stamp = Stamp::from_args(cmd.args(), &state.eval);
+				// Paid for before the scaled stamp is made
+				if let Some((w, h)) = image.as_ref().map(|image| stamp.size(image)) {
+					if !state.charge((w as usize).saturating_mul(h as usize).saturating_mul(state.pen.reach())) {
+						return;
+					}
+				}
+				if let Some(Some(image)) = state.images.get(&path) {
+					for (x, y, color) in stamp.pyxels(image) {
+						state.pen.put(&mut state.pyxels, color, pointer.x() + x, pointer.y() + y);
+					}
+				}
+			}
+
+			"flip" => {
+				let transform = match cmd.arg(0) {
+					"vertical" | "v" => Transform::FlipVertical,
+					_ => Transform::FlipHorizontal,
+				};
+				if let Some(nums) = cmd.numbers(1, usize::MAX, &state.eval) {
+					transform_region(transform, &nums, state);
+				}
+			}
+
+			"rotate" => {
+				let nums = cmd.numbers(0, usize::MAX, &state.eval).unwrap_or_default();
+				if let Some(degrees) = nums.first() {
+					if degrees % 90.0 != 0.0 {
+						println!("toimg: @rotate only turns by multiples of 90 degrees, not {}", degrees);
+						return;
 					}
+					let transform = Transform::Rotate((*degrees as isize).rem_euclid(360) as usize);
+					transform_region(transform, &nums[1..], state);
 				}
-				_ => (),
 			}
 
-			'#' => {
-				let mut k = 0;
-				while k < cmd.rep() {
-					pyxels.push(Pyxel::new(
-						cmd.hex(),
-						pointer.x().abs() as usize,
-						pointer.y().abs() as usize,
-					));
-					pointer.slide(1, 0);
-					k += 1;
+			"shift" | "scroll" => {
+				let nums = cmd.numbers(0, usize::MAX, &state.eval).unwrap_or_default();
+				if nums.len() >= 2 {
+					let (dx, dy) = (nums[0] as isize, nums[1] as isize);
+					let transform = if cmd.name() == "shift" {
+						Transform::Shift(dx, dy)
+					} else {
+						Transform::Scroll(dx, dy)
+					};
+					transform_region(transform, &nums[2..], state);
 				}
 			}
 
-			_ => ()
+			"pointer" => println!("toimg: @pointer needs a name other than {}", pointer.name()),
+
+			name => println!("toimg: Unknown directive @{}", name),
 		}
 
-		i += 1;
+		_ => ()
+	}
+}
+
+// Applies a transform to the region given by nums, paying one step for each pyxel of it
+fn transform_region(transform: Transform, nums: &[f64], state: &mut State) {
+	let (_, _, w, h) = transform_area(nums, &state.pyxels);
+	if state.charge(w.saturating_mul(h)) {
+		transform.apply(&mut state.pyxels, nums);
+	}
+}
+
+// Returns how many steps of work a command takes with a pointer before any arguments are looked at.
+// Moves do one for each pyxel, times how many pyxels the pen paints for each.
+// Puts do that for each pyxel painted, plus the pyxels the pointer steps through advancing after it.
+fn cost(cmd: &Command, pen: &Pen, pointer: &Pointer) -> usize {
+	let put = pen.reach().saturating_add(pointer.advance_steps());
+	match cmd.verb() {
+		'#' | 'x' => cmd.rep().saturating_mul(put),
+		'^' | 'v' | '<' | '>' => cmd.int().unsigned_abs().saturating_mul(pen.reach()).max(1),
+		'@' if cmd.name() == "pick" => cmd.rep().saturating_mul(put),
+		_ => 1,
+	}
+}
+
+// Returns whether every selected pointer runs the command, rather than only the main one.
+// Those that set up the pen, layers or pointers, or paint the whole image, run once.
+fn broadcasts(cmd: &Command) -> bool {
+	match cmd.verb() {
+		'@' => !matches!(
+			cmd.name(),
+			"mirror" | "color" | "pen" | "clip" | "antialias" | "brush" | "layer" | "noise" | "shader" | "scatter"
+				| "flip" | "rotate" | "shift" | "scroll" | "pointer" | "select"
+		),
+		_ => true,
+	}
+}
+
+// Draws an expanded L-system with the turtle. F and G move forward step pyxels painting, f moves without painting,
+// + and - turn left and right by angle, | turns around, and [ and ] save and go back to where the turtle was.
+fn draw_turtle(pointer: &mut Pointer, symbols: &str, angle: f64, step: f64, state: &mut State) {
+	let mut saved = vec![];
+	for c in symbols.chars() {
+		match c {
+			'F' | 'G' | 'f' => {
+				if !state.charge((step.abs() as usize).saturating_mul(state.pen.reach())) {
+					return;
+				}
+				let (dx, dy) = pointer.forward(step);
+				let points = walk(pointer, dx, dy);
+				if c != 'f' {
+					state.pen.stroke(&mut state.pyxels, &points);
+				}
+			}
+			'+' => pointer.turn(angle),
+			'-' => pointer.turn(-angle),
+			'|' => pointer.turn(180.0),
+			'[' => saved.push(pointer.clone()),
+			']' => {
+				if let Some(p) = saved.pop() {
+					*pointer = p;
+				}
+			}
+			_ => (),
+		}
+	}
+}
+
+// Moves the pointer to (x, y) if that is inside its bounds
+fn goto(pointer: &mut Pointer, x: isize, y: isize) {
+	if pointer.in_bounds(x, y) {
+		pointer.set_pos(x, y);
+	} else {
+		println!("toimg: ({}, {}) is outside the pointer's bounds", x, y);
+	}
+}
+
+// Moves the pointer along a line dx, dy pyxels long one step at a time, returning every position it leaves.
+// Each step wraps like any other move.
+fn walk(pointer: &mut Pointer, dx: isize, dy: isize) -> Vec<(isize, isize)> {
+	let mut points = vec![];
+	for step in line(0, 0, dx, dy).windows(2) {
+		if !pointer.stopped() {
+			points.push((pointer.x(), pointer.y()));
+		}
+		pointer.move_pos(step[1].0 - step[0].0, step[1].1 - step[0].1);
+	}
+	points
+}
+
+// Slides the pointer one pyxel at a time, returning every position it leaves
+fn slide_painting(pointer: &mut Pointer, r: isize, d: isize) -> Vec<(isize, isize)> {
+	let mut points = vec![];
+	for _ in 0..r.abs().max(d.abs()) {
+		if !pointer.stopped() {
+			points.push((pointer.x(), pointer.y()));
+		}
+		pointer.slide(r.signum(), d.signum());
+	}
+	points
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::background::Background;
+	use super::super::parser::parse;
+	use super::*;
+
+	// Runs a whole source, header included
+	fn draw(source: &str, settings: &Settings) -> Result<PyxelVec, LimitError> {
+		let (header, comms) = parse(source).unwrap();
+		let mut pointer = Pointer::new();
+		pointer.set_width(header.width());
+		pointer.set_height(header.height());
+		run(&mut pointer, comms, settings)
+	}
+
+	// Returns how many pyxels were drawn
+	fn count(pyxels: &PyxelVec) -> usize {
+		pyxels.layers().iter().map(|l| l.pyxels().len()).sum()
+	}
+
+	// Returns the image a row per string: the letter of each pyxel's color,
+	// a space where nothing shows and ? for colors without a letter
+	fn colors(pyxels: &PyxelVec) -> Vec<String> {
+		let (_, _, w, _) = pyxels.area();
+		let rgba = pyxels.to_vec_u8(&Background::Color([0, 0, 0, 0]), true);
+		let letter = |p: &[u8]| {
+			if p[3] == 0 {
+				return ' ';
+			}
+			"rgbcymw.".chars().find(|c| parse_color(&c.to_string()).is_some_and(|c| c[..] == *p)).unwrap_or('?')
+		};
+		rgba.chunks(w * 4).map(|row| row.chunks(4).map(letter).collect()).collect()
+	}
+
+	#[test]
+	fn brushes_paint_around_the_pointer() {
+		let pyxels = draw("4\n4\n@goto(1, 1)@brush(mask, x.x/.x./x.x)r", &Settings::new()).unwrap();
+		assert_eq!(colors(&pyxels), ["r r ", " r  ", "r r ", "    "]);
+	}
+
+	#[test]
+	fn layers_blend_by_name() {
+		let pyxels = draw("2\n1\ny@layer(top, multiply, 1)@goto(0, 0)m", &Settings::new()).unwrap();
+		assert_eq!(colors(&pyxels), ["r "]);
+	}
+
+	#[test]
+	fn antialiased_lines_blend_their_edges() {
+		let pyxels = draw("5\n3\n@color(r)@antialias(on)@line(4, 2)", &Settings::new()).unwrap();
+		let rgba = pyxels.to_vec_u8(&Background::Color([0, 0, 0, 0]), true);
+		let alpha = |x: usize, y: usize| rgba[(y * 5 + x) * 4 + 3];
+		assert_eq!((alpha(0, 0), alpha(2, 1), alpha(4, 2)), (255, 255, 255));
+		assert_eq!((alpha(1, 0), alpha(1, 1)), (128, 128));
+		assert_eq!(alpha(0, 2), 0);
+	}
+
+	#[test]
+	fn turtle_paints_where_it_goes() {
+		let source = "4\n4\n@color(r)@pen(down)@fd(3)@rt@fd(2)@rt@fd(1)@pen(up)@lt(135)@fd(1.5)g";
+		let pyxels = draw(source, &Settings::new()).unwrap();
+		assert_eq!(colors(&pyxels), ["rrrr", "   r", "   r", "   g"]);
+	}
+
+	#[test]
+	fn lsystems_draw_with_the_turtle_or_run() {
+		let pyxels = draw("4\n4\n@color(r)@goto(0, 3)@lsystem(F, F=F[+F]F, step=2)", &Settings::new()).unwrap();
+		assert_eq!(colors(&pyxels), ["    ", "    ", "  r ", "rrrr"]);
+		let pyxels = draw("4\n1\n@lsystem(A, A=rB, B=g, iter=2, mode=toimg)", &Settings::new()).unwrap();
+		assert_eq!(colors(&pyxels), ["rg  "]);
+	}
+
+	#[test]
+	fn pop_goes_back_to_the_last_push() {
+		let source = "6\n2\n@goto(1, 0)@push@goto(3, 1)@push@lt@pop@color(g)@pen(down)@fd(2)@pen(up)@pop r";
+		let pyxels = draw(source, &Settings::new()).unwrap();
+		assert_eq!(colors(&pyxels), [" r    ", "   gg "]);
+	}
+
+	#[test]
+	fn goto_and_jump_to_marks() {
+		let source = "4\n2\n@goto(2, 1)@mark(a)@goto(0, 0)r@jump(a)g@jump(nope)b@goto(9, 9)@goto(1, 2/2)c";
+		let pyxels = draw(source, &Settings::new()).unwrap();
+		assert_eq!(colors(&pyxels), ["r   ", " cgb"]);
+	}
+
+	#[test]
+	fn puts_advance_the_way_they_are_told() {
+		let pyxels = draw("4\n2\n@advance(left)@goto(3, 0)2r@advance(2, 0)@goto(0, 1)2g", &Settings::new()).unwrap();
+		assert_eq!(colors(&pyxels), ["  rr", "g g "]);
+		let pyxels = draw("3\n3\n@advance(down-right)3r", &Settings::new()).unwrap();
+		assert_eq!(colors(&pyxels), ["r  ", " r ", "  r"]);
+	}
+
+	#[test]
+	fn clips_keep_puts_inside() {
+		let pyxels = draw("4\n2\n@clip(rect, 1, 0, 2, 2)8r@clip(off)@goto(0, 1)g", &Settings::new()).unwrap();
+		assert_eq!(colors(&pyxels), [" rr ", "grr "]);
+		let pyxels = draw("4\n1\nrgrg@clip(color, g)@goto(0, 0)4b", &Settings::new()).unwrap();
+		assert_eq!(colors(&pyxels), ["rbrb"]);
+	}
+
+	#[test]
+	fn lsystem_runs_once_per_pointer() {
+		let pyxels = draw("8\n8\n@pointer(b, 0, 4)\n@select(all)\n@lsystem(A, A=rr, mode=toimg)\n", &Settings::new()).unwrap();
+		assert_eq!(count(&pyxels), 4);
+	}
+
+	#[test]
+	fn goto_past_the_edge_when_infinite() {
+		let mut settings = Settings::new();
+		settings.set_infinite(true, true);
+		let pyxels = draw("4\n4\n@goto(-10, 20)r@goto(5, 20)r", &settings).unwrap();
+		assert_eq!(pyxels.area(), (-10, 20, 16, 1));
+	}
+
+	#[test]
+	fn padding_does_not_overflow() {
+		let mut settings = Settings::new();
+		settings.set_infinite(true, true);
+		settings.set_padding(usize::MAX);
+		assert!(matches!(draw("4\n4\nr", &settings), Err(LimitError::Pixels(..))));
+	}
+
+	// Returns Settings that stop after n commands
+	fn capped(n: usize) -> Settings {
+		let mut limits = Limits::new();
+		limits.set_commands(n);
+		let mut settings = Settings::new();
+		settings.set_limits(limits);
+		settings
+	}
+
+	#[test]
+	fn long_advances_are_paid_for() {
+		let result = draw("8\n8\n@advance(1000000000000000, 0)r", &Settings::new());
+		assert_eq!(result.err(), Some(LimitError::Commands(10_000_000)));
+	}
+
+	#[test]
+	fn lsystem_iterations_are_paid_for() {
+		let result = draw("8\n8\n@lsystem(F, iter=1e15)", &capped(1000));
+		assert_eq!(result.err(), Some(LimitError::Commands(1000)));
+	}
+
+	#[test]
+	fn stamp_is_paid_for_before_scaling() {
+		let dir = std::env::temp_dir().join(format!("toimg-stamp-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		RgbaImage::from_pixel(2, 2, image::Rgba([255, 0, 0, 255])).save(dir.join("stamp.png")).unwrap();
+		let mut settings = capped(1000);
+		settings.set_dir(dir.clone());
+		let result = draw("8\n8\n@stamp(stamp.png, scale=200000)", &settings);
+		assert_eq!(result.err(), Some(LimitError::Commands(1000)));
+		assert!(draw("8\n8\n@stamp(stamp.png, scale=2)", &settings).is_ok());
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn stamp_is_paid_for_before_decoding() {
+		let dir = std::env::temp_dir().join(format!("toimg-decode-{}", std::process::id()));
+		let inner = dir.join("inner");
+		std::fs::create_dir_all(&inner).unwrap();
+		RgbaImage::from_pixel(200, 200, image::Rgba([255, 0, 0, 255])).save(inner.join("big.png")).unwrap();
+		let mut settings = capped(1000);
+		settings.set_dir(inner.clone());
+		assert_eq!(draw("8\n8\n@stamp(big.png)", &settings).err(), Some(LimitError::Commands(1000)));
+		// Outside the input's directory nothing is read, so nothing is paid or drawn
+		settings.set_dir(inner.join("deeper"));
+		assert_eq!(count(&draw("8\n8\n@stamp(../big.png)", &settings).unwrap()), 0);
+		let absolute = format!("8\n8\n@stamp({})", inner.join("big.png").display());
+		assert_eq!(count(&draw(&absolute, &settings).unwrap()), 0);
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn rotate_only_turns_by_right_angles() {
+		let pyxels = draw("4\n4\nrr@rotate(45)", &Settings::new()).unwrap();
+		let (x, y, w, h) = pyxels.area();
+		let flat = pyxels.flatten_region(x, y, w, h);
+		assert!(flat[0][3] > 0.0 && flat[1][3] > 0.0);
+	}
+
+	#[test]
+	fn lsystems_stop_at_their_limit() {
+		let result = draw("8\n8\n@lsystem(F, F=FF, iter=20, limit=1000)", &Settings::new());
+		assert_eq!(result.err(), Some(LimitError::Symbols(1000)));
+		let result = draw("8\n8\n@lsystem(F, F=FF, iter=20)", &capped(1000));
+		assert_eq!(result.err(), Some(LimitError::Commands(1000)));
+	}
+
+	#[test]
+	fn every_pointer_pays() {
+		let source = "8\n8\n@pointer(b, 0, 2)\n@pointer(c, 0, 4)\n@select(all)\n40r";
+		assert!(draw(source, &capped(300)).is_ok());
+		assert_eq!(draw(source, &capped(200)).err(), Some(LimitError::Commands(200)));
+	}
+
+	#[test]
+	fn transforms_pay_for_their_region() {
+		assert!(draw("8\n8\n@flip(h)", &capped(100)).is_ok());
+		assert_eq!(draw("8\n8\n@flip(h)", &capped(50)).err(), Some(LimitError::Commands(50)));
+		assert!(draw("8\n8\n@flip(h, 0, 0, 4, 4)", &capped(50)).is_ok());
+	}
+
+	// Returns Settings that stop after the given number of seconds, and no cap on commands
+	fn timed(seconds: f64) -> Settings {
+		let mut limits = Limits::new();
+		limits.set_commands(0);
+		limits.set_time(seconds);
+		let mut settings = Settings::new();
+		settings.set_limits(limits);
+		settings
+	}
+
+	#[test]
+	fn shader_stops_on_time() {
+		let started = std::time::Instant::now();
+		let result = draw("3000\n3000\n@shader(sin(x*y)^2, cos(x+y)^2, u*v)", &timed(0.2));
+		assert_eq!(result.err(), Some(LimitError::Time(0.2)));
+		assert!(started.elapsed().as_secs_f64() < 5.0);
+	}
+
+	#[test]
+	fn noise_stops_on_time() {
+		let result = draw("3000\n3000\n@noise(perlin, octaves=8)", &timed(0.2));
+		assert_eq!(result.err(), Some(LimitError::Time(0.2)));
 	}
-	pyxels
 }
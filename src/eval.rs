@@ -0,0 +1,67 @@
+use super::random::*;
+use meval::{Context, Expr};
+use std::cell::{RefCell, RefMut};
+use std::rc::Rc;
+
+// Evaluates directive arguments while the commands run.
+// Besides meval's usual functions they may use rand() and randint(a, b), which draw from one seeded sequence.
+pub struct Eval {
+	context: Context<'static>,
+	random: Rc<RefCell<Random>>,
+}
+
+impl Eval {
+	// Returns a new Eval. Takes the seed.
+	pub fn new(seed: u64) -> Self {
+		let random = Rc::new(RefCell::new(Random::new(seed)));
+		let mut context = Context::new();
+		let r = random.clone();
+		context.func("rand", move |_| r.borrow_mut().next_f64());
+		let r = random.clone();
+		context.func2("randint", move |a, b| r.borrow_mut().range(a as i64, b as i64) as f64);
+		Eval {
+			context,
+			random,
+		}
+	}
+
+	// Returns the random sequence, for commands that pick things at random themselves
+	pub fn random(&self) -> RefMut<'_, Random> {
+		self.random.borrow_mut()
+	}
+
+	// Evaluates an expression, or returns None if it is not one
+	pub fn eval(&self, s: &str) -> Option<f64> {
+		self.eval_expr(&self.expr(s)?, &[])
+	}
+
+	// Evaluates every value, written in the arguments of the directive called name.
+	// Prints the first that is not a number and returns None.
+	pub fn numbers<'a, I>(&self, name: &str, values: I) -> Option<Vec<f64>>
+	where
+		I: IntoIterator<Item = &'a str>,
+	{
+		let mut v = vec![];
+		for value in values {
+			match self.eval(value) {
+				Some(n) => v.push(n),
+				None => {
+					println!("toimg: Invalid number in @{}: {}", name, value);
+					return None;
+				}
+			}
+		}
+		Some(v)
+	}
+
+	// Reads an expression to be evaluated many times, or returns None if it is not one
+	pub fn expr(&self, s: &str) -> Option<Expr> {
+		// meval has no functions without arguments
+		s.replace("rand()", "rand(0)").parse::<Expr>().ok()
+	}
+
+	// Evaluates a read expression with the given variables
+	pub fn eval_expr(&self, expr: &Expr, vars: &[(&str, f64)]) -> Option<f64> {
+		expr.eval_with_context((vars.to_vec(), &self.context)).ok()
+	}
+}
@@ -0,0 +1,155 @@
+use super::pyxel::*;
+use std::collections::HashMap;
+
+// How big the squares --heatmap sums up are, and how many of them it prints
+pub const HEATMAP_SQUARE: usize = 8;
+pub const HEATMAP_SQUARES: usize = 5;
+
+// How many pyxels were composited onto each pixel of the output image
+pub struct Heatmap {
+	x: isize,
+	y: isize,
+	width: usize,
+	height: usize,
+	counts: Vec<usize>,
+}
+
+impl Heatmap {
+	// Counts the pyxels composited at each pixel of the image, erasers included
+	pub fn new(pyxels: &PyxelVec) -> Self {
+		let (x, y, width, height) = pyxels.area();
+		let mut heatmap = Heatmap {
+			x,
+			y,
+			width,
+			height,
+			counts: vec![0; width * height],
+		};
+		for p in shown(pyxels) {
+			if let Some(i) = heatmap.index(p) {
+				heatmap.counts[i] += 1;
+			}
+		}
+		heatmap
+	}
+
+	// Returns where a pyxel is in counts, if it is in the image
+	fn index(&self, p: &Pyxel) -> Option<usize> {
+		let (px, py) = (p.x() - self.x, p.y() - self.y);
+		if px < 0 || py < 0 || px as usize >= self.width || py as usize >= self.height {
+			return None;
+		}
+		Some(py as usize * self.width + px as usize)
+	}
+
+	// Returns the counts as RGB. Nothing drawn is black, one pyxel is dark blue,
+	// and more go through blue, red and yellow to white for the most on any pixel.
+	pub fn to_vec_u8(&self) -> Vec<u8> {
+		let stops: [[f32; 3]; 5] = [[0.0, 0.0, 128.0], [0.0, 0.0, 255.0], [255.0, 0.0, 0.0], [255.0, 255.0, 0.0], [255.0, 255.0, 255.0]];
+		let max = self.counts.iter().copied().max().unwrap_or(0);
+		let mut v = Vec::with_capacity(self.counts.len() * 3);
+		for &n in self.counts.iter() {
+			if n == 0 {
+				v.extend_from_slice(&[0, 0, 0]);
+				continue;
+			}
+			let t = if max > 1 { (n - 1) as f32 / (max - 1) as f32 } else { 0.0 };
+			let s = t * (stops.len() - 1) as f32;
+			let i = (s.floor() as usize).min(stops.len() - 2);
+			let f = s - i as f32;
+			for (a, b) in stops[i].iter().zip(stops[i + 1].iter()) {
+				v.push((a + (b - a) * f).round() as u8);
+			}
+		}
+		v
+	}
+
+	// Prints how much was drawn over, then the size by size squares of the image with the most overdraw, most first.
+	// A pixel's overdraw is every pyxel on it after the first.
+	pub fn print_summary(&self, pyxels: &PyxelVec, size: usize, count: usize) {
+		let drawn: usize = self.counts.iter().sum();
+		let covered = self.counts.iter().filter(|&&n| n > 0).count();
+		let overdraw: usize = self.counts.iter().map(|n| n.saturating_sub(1)).sum();
+		let max = self.counts.iter().copied().max().unwrap_or(0);
+		println!(
+			"{} pyxels drawn onto {} pixels, {} of them over others, up to {} on one pixel",
+			drawn, covered, overdraw, max
+		);
+		let (across, down) = ((self.width + size - 1) / size, (self.height + size - 1) / size);
+		let mut regions = vec![];
+		for ry in 0..down {
+			for rx in 0..across {
+				let (mut total, mut most) = (0, 0);
+				for y in ry * size..((ry + 1) * size).min(self.height) {
+					for x in rx * size..((rx + 1) * size).min(self.width) {
+						let n = self.counts[y * self.width + x];
+						total += n.saturating_sub(1);
+						most = most.max(n);
+					}
+				}
+				if total > 0 {
+					regions.push((rx * size, ry * size, total, most));
+				}
+			}
+		}
+		regions.sort_by(|a, b| b.2.cmp(&a.2).then((a.1, a.0).cmp(&(b.1, b.0))));
+		regions.truncate(count);
+		for (x, y, total, most) in regions {
+			// The line that drew the most pyxels there
+			let mut lines: HashMap<usize, usize> = HashMap::new();
+			for p in shown(pyxels) {
+				if let Some(i) = self.index(p) {
+					let (px, py) = (i % self.width, i / self.width);
+					if px >= x && py >= y && px < x + size && py < y + size {
+						*lines.entry(p.span().line()).or_insert(0) += 1;
+					}
+				}
+			}
+			let line = lines.iter().max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0))).map_or(0, |(l, _)| *l);
+			println!(
+				" ({}, {})-({}, {}): {} over others, up to {} on one pixel, most from line {}",
+				x,
+				y,
+				(x + size).min(self.width) - 1,
+				(y + size).min(self.height) - 1,
+				total,
+				most,
+				line
+			);
+		}
+	}
+}
+
+// Returns the pyxels that compositing the layers puts on the image. Layers with an opacity of 0 don't show,
+// and neither do pyxels with no alpha.
+fn shown(pyxels: &PyxelVec) -> impl Iterator<Item = &Pyxel> {
+	pyxels.layers().iter().filter(|l| l.opacity() > 0.0).flat_map(|l| l.pyxels().iter()).filter(|p| p.a() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn counts_what_shows() {
+		let mut pyxels = PyxelVec::new(2, 1);
+		pyxels.push(Pyxel::new([255, 0, 0, 255], 0, 0));
+		pyxels.push(Pyxel::new([255, 0, 0, 255], 0, 0));
+		pyxels.push(Pyxel::eraser(255, 1, 0));
+		pyxels.push(Pyxel::new([255, 0, 0, 0], 1, 0));
+		pyxels.select_layer("hidden");
+		pyxels.layer_mut().set_opacity(0.0);
+		pyxels.push(Pyxel::new([255, 0, 0, 255], 1, 0));
+		assert_eq!(Heatmap::new(&pyxels).counts, vec![2, 1]);
+	}
+
+	#[test]
+	fn colors() {
+		let mut pyxels = PyxelVec::new(3, 1);
+		pyxels.push(Pyxel::new([255, 0, 0, 255], 1, 0));
+		for _ in 0..3 {
+			pyxels.push(Pyxel::new([255, 0, 0, 255], 2, 0));
+		}
+		assert_eq!(Heatmap::new(&pyxels).to_vec_u8(), vec![0, 0, 0, 0, 0, 128, 255, 255, 255]);
+	}
+}
@@ -0,0 +1,164 @@
+use super::pyxel::*;
+
+// How a layer's colors are combined with the layers below it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Blend {
+	Normal,
+	Multiply,
+	Screen,
+	Overlay,
+	Add,
+	Darken,
+	Lighten,
+}
+
+impl Blend {
+	// Returns the Blend with the given name, if there is one
+	pub fn from_name(s: &str) -> Option<Blend> {
+		match s {
+			"normal" => Some(Blend::Normal),
+			"multiply" => Some(Blend::Multiply),
+			"screen" => Some(Blend::Screen),
+			"overlay" => Some(Blend::Overlay),
+			"add" => Some(Blend::Add),
+			"darken" => Some(Blend::Darken),
+			"lighten" => Some(Blend::Lighten),
+			_ => None,
+		}
+	}
+
+	// Blends a channel of the layer (cs) with the same channel below it (cb). Both go from 0 to 1.
+	pub fn channel(&self, cb: f32, cs: f32) -> f32 {
+		match self {
+			Blend::Normal => cs,
+			Blend::Multiply => cb * cs,
+			Blend::Screen => cb + cs - cb * cs,
+			Blend::Overlay => {
+				if cb <= 0.5 {
+					2.0 * cb * cs
+				} else {
+					1.0 - 2.0 * (1.0 - cb) * (1.0 - cs)
+				}
+			}
+			Blend::Add => (cb + cs).min(1.0),
+			Blend::Darken => cb.min(cs),
+			Blend::Lighten => cb.max(cs),
+		}
+	}
+}
+
+// A named list of Pyxels that is composited as a whole
+#[derive(Debug, Clone)]
+pub struct Layer {
+	name: String,
+	blend: Blend,
+	opacity: f32,
+	pyxels: Vec<Pyxel>,
+}
+
+impl Layer {
+	// Returns a new, empty Layer with normal blending and full opacity
+	pub fn new(name: &str) -> Layer {
+		Layer {
+			name: name.to_string(),
+			blend: Blend::Normal,
+			opacity: 1.0,
+			pyxels: vec![],
+		}
+	}
+
+	// Returns respective values
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	pub fn blend(&self) -> Blend {
+		self.blend
+	}
+
+	pub fn opacity(&self) -> f32 {
+		self.opacity
+	}
+
+	pub fn pyxels(&self) -> &[Pyxel] {
+		&self.pyxels
+	}
+
+	pub fn pyxels_mut(&mut self) -> &mut Vec<Pyxel> {
+		&mut self.pyxels
+	}
+
+	// Set respective values
+	pub fn set_blend(&mut self, blend: Blend) {
+		self.blend = blend;
+	}
+
+	pub fn set_opacity(&mut self, opacity: f32) {
+		self.opacity = opacity.clamp(0.0, 1.0);
+	}
+
+	// Composites the pyxels inside a region over transparency.
+	// Returns straight (not premultiplied) colors, one per position in row order.
+	pub fn flatten_region(&self, x: isize, y: isize, w: usize, h: usize) -> Vec<[f32; 4]> {
+		let mut v = vec![[0f32; 4]; w * h];
+		for p in self.pyxels.iter() {
+			if p.x() < x || p.y() < y || p.x() >= x + w as isize || p.y() >= y + h as isize {
+				continue;
+			}
+			let src = p.color_f32();
+			let dst = &mut v[(p.y() - y) as usize * w + (p.x() - x) as usize];
+			if p.erase() {
+				dst[3] *= 1.0 - src[3];
+				continue;
+			}
+			let a = src[3] + dst[3] * (1.0 - src[3]);
+			if a > 0.0 {
+				for c in 0..3 {
+					dst[c] = (src[c] * src[3] + dst[c] * dst[3] * (1.0 - src[3])) / a;
+				}
+			}
+			dst[3] = a;
+		}
+		v
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::background::Background;
+	use super::*;
+
+	const BLACK: Background = Background::Color([0, 0, 0, 255]);
+
+	#[test]
+	fn blend_channels() {
+		assert_eq!(Blend::Multiply.channel(0.5, 0.5), 0.25);
+		assert_eq!(Blend::Screen.channel(0.5, 0.5), 0.75);
+		assert_eq!(Blend::Overlay.channel(0.25, 0.5), 0.25);
+		assert_eq!(Blend::Overlay.channel(0.75, 0.5), 0.75);
+		assert_eq!(Blend::Add.channel(0.75, 0.5), 1.0);
+		assert_eq!(Blend::Darken.channel(0.25, 0.5), 0.25);
+		assert_eq!(Blend::Lighten.channel(0.25, 0.5), 0.5);
+	}
+
+	#[test]
+	fn layers_blend_with_what_is_below() {
+		let mut pyxels = PyxelVec::new(2, 1);
+		pyxels.push(Pyxel::new([255, 255, 0, 255], 0, 0));
+		pyxels.select_layer("top");
+		pyxels.layer_mut().set_blend(Blend::Multiply);
+		pyxels.push(Pyxel::new([255, 0, 255, 255], 0, 0));
+		pyxels.push(Pyxel::new([255, 0, 255, 255], 1, 0));
+		assert_eq!(pyxels.to_vec_u8(&BLACK, false), [255, 0, 0, 0, 0, 0]);
+		assert_eq!(pyxels.layer_to_vec_u8(1, &BLACK, false), [0, 0, 0, 0, 0, 0]);
+	}
+
+	#[test]
+	fn opacity_lets_the_layers_below_through() {
+		let mut pyxels = PyxelVec::new(1, 1);
+		pyxels.select_layer("top");
+		pyxels.layer_mut().set_opacity(0.5);
+		pyxels.push(Pyxel::new([255, 255, 255, 255], 0, 0));
+		assert_eq!(pyxels.to_vec_u8(&BLACK, false), [128, 128, 128]);
+	}
+}
@@ -0,0 +1,210 @@
+use std::fmt;
+use std::time::Instant;
+
+// Caps on how much work a source may make toimg do. A cap of 0 is no cap.
+#[derive(Debug, Copy, Clone)]
+pub struct Limits {
+	commands: usize,
+	loops: usize,
+	pixels: usize,
+	time: f64,
+}
+
+impl Limits {
+	// Returns the default Limits. Takes no arguments.
+	pub fn new() -> Self {
+		Limits {
+			commands: 10_000_000,
+			loops: 1_000_000,
+			pixels: 4096 * 4096,
+			time: 10.0,
+		}
+	}
+
+	// Returns respective values
+	pub fn commands(&self) -> usize {
+		self.commands
+	}
+
+	pub fn loops(&self) -> usize {
+		self.loops
+	}
+
+	pub fn pixels(&self) -> usize {
+		self.pixels
+	}
+
+	// Returns time. How many seconds the commands may run for.
+	pub fn time(&self) -> f64 {
+		self.time
+	}
+
+	// Set respective values
+	pub fn set_commands(&mut self, commands: usize) {
+		self.commands = commands;
+	}
+
+	pub fn set_loops(&mut self, loops: usize) {
+		self.loops = loops;
+	}
+
+	pub fn set_pixels(&mut self, pixels: usize) {
+		self.pixels = pixels;
+	}
+
+	pub fn set_time(&mut self, time: f64) {
+		self.time = time;
+	}
+
+	// Returns an error if an image of the given size has more pixels than allowed
+	pub fn check_pixels(&self, width: usize, height: usize) -> Result<(), LimitError> {
+		match width.checked_mul(height) {
+			Some(n) if self.pixels() == 0 || n <= self.pixels() => Ok(()),
+			_ => Err(LimitError::Pixels(width, height, self.pixels())),
+		}
+	}
+}
+
+// The cap a source went over
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitError {
+	Commands(usize),
+	Loops(usize),
+	Pixels(usize, usize, usize),
+	Time(f64),
+	Symbols(usize),
+}
+
+impl fmt::Display for LimitError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			LimitError::Commands(n) => write!(f, "Ran more than {} commands (see --max-commands)", n),
+			LimitError::Loops(n) => write!(f, "Looped more than {} times (see --max-loops)", n),
+			LimitError::Pixels(w, h, n) => write!(f, "A {}x{} image is more than {} pixels (see --max-pixels)", w, h, n),
+			LimitError::Time(t) => write!(f, "Ran for more than {} seconds (see --timeout)", t),
+			LimitError::Symbols(n) => write!(f, "An L-system grew past {} symbols (see limit= in @lsystem)", n),
+		}
+	}
+}
+
+impl std::error::Error for LimitError {}
+
+// Counts the work done so far against the Limits
+#[derive(Debug, Clone)]
+pub struct Budget {
+	limits: Limits,
+	commands: usize,
+	loops: usize,
+	started: Instant,
+}
+
+impl Budget {
+	// Returns a new Budget with nothing spent, starting the clock
+	pub fn new(limits: Limits) -> Self {
+		Budget {
+			limits,
+			commands: 0,
+			loops: 0,
+			started: Instant::now(),
+		}
+	}
+
+	// Returns how many more commands may run
+	pub fn remaining(&self) -> usize {
+		if self.limits.commands() == 0 {
+			usize::MAX
+		} else {
+			self.limits.commands().saturating_sub(self.commands)
+		}
+	}
+
+	// Counts n commands, or steps of a command that does many things at once, and checks the clock
+	pub fn charge(&mut self, n: usize) -> Result<(), LimitError> {
+		self.commands = self.commands.saturating_add(n);
+		let limit = self.limits.commands();
+		if limit > 0 && self.commands > limit {
+			return Err(LimitError::Commands(limit));
+		}
+		let limit = self.limits.time();
+		if limit > 0.0 && self.started.elapsed().as_secs_f64() > limit {
+			return Err(LimitError::Time(limit));
+		}
+		Ok(())
+	}
+
+	// Counts one more time round a loop
+	pub fn charge_loop(&mut self) -> Result<(), LimitError> {
+		self.loops += 1;
+		let limit = self.limits.loops();
+		if limit > 0 && self.loops > limit {
+			return Err(LimitError::Loops(limit));
+		}
+		self.charge(1)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn charge_up_to_the_limit() {
+		let mut limits = Limits::new();
+		limits.set_commands(10);
+		let mut budget = Budget::new(limits);
+		assert_eq!(budget.charge(4), Ok(()));
+		assert_eq!(budget.remaining(), 6);
+		assert_eq!(budget.charge(6), Ok(()));
+		assert_eq!(budget.charge(1), Err(LimitError::Commands(10)));
+		assert_eq!(budget.remaining(), 0);
+	}
+
+	#[test]
+	fn charge_does_not_overflow() {
+		let mut budget = Budget::new(Limits::new());
+		assert_eq!(budget.charge(usize::MAX), Err(LimitError::Commands(10_000_000)));
+		assert_eq!(budget.charge(usize::MAX), Err(LimitError::Commands(10_000_000)));
+	}
+
+	#[test]
+	fn no_cap() {
+		let mut limits = Limits::new();
+		limits.set_commands(0);
+		limits.set_loops(0);
+		let mut budget = Budget::new(limits);
+		assert_eq!(budget.remaining(), usize::MAX);
+		assert_eq!(budget.charge(usize::MAX), Ok(()));
+		for _ in 0..1000 {
+			assert_eq!(budget.charge_loop(), Ok(()));
+		}
+	}
+
+	#[test]
+	fn charge_loop_counts_loops() {
+		let mut limits = Limits::new();
+		limits.set_loops(3);
+		let mut budget = Budget::new(limits);
+		for _ in 0..3 {
+			assert_eq!(budget.charge_loop(), Ok(()));
+		}
+		assert_eq!(budget.charge_loop(), Err(LimitError::Loops(3)));
+	}
+
+	#[test]
+	fn charge_checks_the_clock() {
+		let mut limits = Limits::new();
+		limits.set_time(0.001);
+		let mut budget = Budget::new(limits);
+		std::thread::sleep(std::time::Duration::from_millis(5));
+		assert_eq!(budget.charge(1), Err(LimitError::Time(0.001)));
+	}
+
+	#[test]
+	fn check_pixels() {
+		let mut limits = Limits::new();
+		limits.set_pixels(100);
+		assert_eq!(limits.check_pixels(10, 10), Ok(()));
+		assert_eq!(limits.check_pixels(10, 11), Err(LimitError::Pixels(10, 11, 100)));
+		assert_eq!(limits.check_pixels(usize::MAX, 2), Err(LimitError::Pixels(usize::MAX, 2, 100)));
+	}
+}
@@ -0,0 +1,181 @@
+use super::eval::*;
+
+// A string rewritten by rules a number of times, then drawn by the turtle or run as commands
+#[derive(Debug, Clone)]
+pub struct LSystem {
+	axiom: String,
+	rules: Vec<(char, String)>,
+	iterations: usize,
+	angle: f64,
+	step: f64,
+	turtle: bool,
+	limit: usize,
+}
+
+impl LSystem {
+	// Returns a new LSystem with no rules
+	pub fn new(axiom: String) -> Self {
+		LSystem {
+			axiom,
+			rules: vec![],
+			iterations: 1,
+			angle: 90.0,
+			step: 2.0,
+			turtle: true,
+			limit: 100000,
+		}
+	}
+
+	// Builds an LSystem from directive arguments: the axiom followed by rules like F=F+F-F
+	// and the options iter=n, angle=n, step=n, mode=turtle|toimg and limit=n
+	pub fn from_args(args: &[String], eval: &Eval) -> Option<Self> {
+		let mut lsystem = LSystem::new(args.first()?.to_string());
+		for arg in args.iter().skip(1) {
+			let (key, value) = match arg.find('=') {
+				Some(i) => (arg[..i].trim(), arg[i + 1..].trim()),
+				None => continue,
+			};
+			let mut chars = key.chars();
+			if let (Some(symbol), None) = (chars.next(), chars.next()) {
+				lsystem.add_rule(symbol, value.to_string());
+				continue;
+			}
+			match key {
+				"iter" => {
+					if let Some(n) = eval.eval(value) {
+						lsystem.set_iterations(n.max(0.0) as usize);
+					}
+				}
+				"angle" => {
+					if let Some(n) = eval.eval(value) {
+						lsystem.set_angle(n);
+					}
+				}
+				"step" => {
+					if let Some(n) = eval.eval(value) {
+						lsystem.set_step(n);
+					}
+				}
+				"mode" => lsystem.set_turtle(value != "toimg"),
+				"limit" => {
+					if let Some(n) = eval.eval(value) {
+						lsystem.set_limit(n.max(0.0) as usize);
+					}
+				}
+				_ => (),
+			}
+		}
+		Some(lsystem)
+	}
+
+	// Returns respective values
+	pub fn angle(&self) -> f64 {
+		self.angle
+	}
+
+	pub fn step(&self) -> f64 {
+		self.step
+	}
+
+	// Returns turtle. Whether the expansion is drawn by the turtle rather than run as commands.
+	pub fn turtle(&self) -> bool {
+		self.turtle
+	}
+
+	pub fn limit(&self) -> usize {
+		self.limit
+	}
+
+	// Set respective values
+	pub fn add_rule(&mut self, symbol: char, replacement: String) {
+		self.rules.push((symbol, replacement));
+	}
+
+	pub fn set_iterations(&mut self, iterations: usize) {
+		self.iterations = iterations;
+	}
+
+	pub fn set_angle(&mut self, angle: f64) {
+		self.angle = angle;
+	}
+
+	pub fn set_step(&mut self, step: f64) {
+		self.step = step;
+	}
+
+	pub fn set_turtle(&mut self, turtle: bool) {
+		self.turtle = turtle;
+	}
+
+	// The most symbols the expansion may grow to
+	pub fn set_limit(&mut self, limit: usize) {
+		self.limit = limit;
+	}
+
+	// Rewrites the axiom by the rules, every symbol at once, as many times as asked.
+	// Each time round it first calls charge with how many symbols it rewrites, and stops if that returns false.
+	// Returns None if it would grow past the limit or was stopped.
+	pub fn expand<F>(&self, mut charge: F) -> Option<String>
+	where
+		F: FnMut(usize) -> bool,
+	{
+		let mut s = self.axiom.clone();
+		for _ in 0..self.iterations {
+			if !charge(s.len().max(1)) {
+				return None;
+			}
+			let mut next = String::new();
+			let mut count = 0;
+			for c in s.chars() {
+				match self.rules.iter().find(|(symbol, _)| *symbol == c) {
+					Some((_, replacement)) => {
+						next.push_str(replacement);
+						count += replacement.chars().count();
+					}
+					None => {
+						next.push(c);
+						count += 1;
+					}
+				}
+				if count > self.limit {
+					return None;
+				}
+			}
+			s = next;
+		}
+		Some(s)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn algae() -> LSystem {
+		let mut lsystem = LSystem::new("A".to_string());
+		lsystem.add_rule('A', "AB".to_string());
+		lsystem.add_rule('B', "A".to_string());
+		lsystem
+	}
+
+	#[test]
+	fn rewrites_every_symbol_at_once() {
+		let mut lsystem = algae();
+		lsystem.set_iterations(4);
+		assert_eq!(lsystem.expand(|_| true), Some("ABAABABA".to_string()));
+		lsystem.set_iterations(0);
+		assert_eq!(lsystem.expand(|_| true), Some("A".to_string()));
+	}
+
+	#[test]
+	fn stops_at_the_limit_or_when_charging_fails() {
+		let mut lsystem = algae();
+		lsystem.set_iterations(10);
+		lsystem.set_limit(50);
+		assert_eq!(lsystem.expand(|_| true), None);
+		lsystem.set_limit(100000);
+		let mut charged = vec![];
+		assert_eq!(lsystem.expand(|n| { charged.push(n); charged.len() < 3 }), None);
+		assert_eq!(charged, [1, 2, 3]);
+	}
+}
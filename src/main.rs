@@ -1,23 +1,46 @@
+mod background;
+use background::*;
+mod blame;
+mod brush;
 mod cmd;
-use cmd::*;
+mod eval;
+mod heatmap;
+use heatmap::*;
+mod layer;
+mod limits;
+use limits::*;
+mod lsystem;
+mod mask;
+mod noise;
 mod parser;
-use parser::*;
+mod pen;
 mod pointer;
-use pointer::*;
 mod pyxel;
-use pyxel::*;
+mod random;
+mod raster;
+mod settings;
+use settings::*;
+mod shader;
+mod stamp;
+mod symmetry;
+mod transform;
+mod trace;
+use trace::*;
+mod traversal;
 
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs::File;
 use std::io::BufReader;
 use std::io::prelude::*;
-use image;
 
 fn display_help() {
-	println!("Usage: toimg [OPTION]... [FILE]...\n
+	println!("Usage: toimg [OPTION]... [FILE]...
+  or:  toimg blame [OPTION]... [FILE] [X Y]\n
 Converts text to image and saves as an image file in the current directory.\n
 Default format is BMP.\n
+With blame, draws the file without saving it and shows which commands drew the pixel at (x, y) of the image,
+last one first: where each is in the file (line:column), its layer, its color and what was written there.\n
  -h, --help                   Displays this help.
  -f, --format [FORMAT]        The format to save the image in (default: .bmp). Available formats:
                                  .png
@@ -25,7 +48,41 @@ Default format is BMP.\n
                                  .jpg
                                  .ico
                                  .tif
+                              WebP can't be saved: the image library toimg is built with can only read it.
  -n, --name [NAME]            The name of the output file (else saves as [FILE] name). Will overwrite the file if it already exists.
+ -b, --background [COLOR]     The color behind everything drawn (default: w). Takes a color code (#88f, #0008), a color command
+                              with an optional tone (b, lr, .), transparent, or checker for a see-through preview pattern.
+                              PNG, BMP, ICO and TIFF images with a see-through background are saved with an alpha channel.
+     --transparent            Makes the background transparent, keeping its color.
+     --dark                   Makes the background black.
+     --seed [N]               Where random numbers start from (default: 0). The same seed always draws the same image.
+     --time [T]               What t is in @shader expressions (default: 0), for drawing frames of an animation.
+     --infinite               Lets the pointer go anywhere, past the edges and to negative positions, and grows the image
+                              to fit everything drawn. The same as a width and height of *.
+     --padding [N]            Leaves n pyxels of background around an image grown to fit (default: 0).
+     --max-commands [N]       Stops with an error after running more than n commands (default: 10000000). A put or move
+                              of several pyxels counts each, times the pyxels the brush and mirrors paint for each, plus
+                              the pyxels @advance steps through after it. Every selected pointer counts what it runs.
+     --max-loops [N]          Stops with an error after going round loops more than n times in all (default: 1000000).
+     --max-pixels [N]         Stops with an error if the image would have more than n pixels (default: 16777216).
+     --timeout [SECONDS]      Stops with an error if the commands take longer than this to run (default: 10).
+                              For the limits, 0 means no limit. They can't be set from the input file.
+     --trace                  Logs every command to stderr as it runs: its index, where it is in the file (line:column),
+                              the pointer before and after, the pointer's bounds, which ways it is reversed (X, Y)
+                              and the loops it is inside, as the index of each [ and how many more times it goes round.
+     --trace-line [N]         Only logs commands on line n of the file. Implies --trace.
+     --trace-command [N]      Only logs the command at index n. Implies --trace. Commands a directive expands to are
+                              numbered after its own index, e.g. 5.2 is the third command the one at 5 expanded to,
+                              and giving 5 logs them all.
+     --json                   With blame, prints what drew the pixel as JSON instead, or what drew every pixel if no
+                              x and y are given: {{\"width\", \"height\", \"pixels\": [{{\"x\", \"y\", \"drawn\": [{{\"layer\",
+                              \"line\", \"column\", \"end_line\", \"end_column\", \"text\", \"color\"}}, ...]}}, ...]}}.
+     --layers                 Also save every layer as its own image, named [NAME]-[LAYER].
+     --heatmap                Also save an image named [NAME]-heatmap showing how many pyxels were drawn onto each pixel:
+                              black for none, dark blue for one, then through blue, red and yellow to white for the most.
+                              Erasers count, but nothing on a layer with an opacity of 0 does, as it doesn't show.
+                              Prints how many were drawn over others, and the 5 squares of 8 by 8 pixels with the most,
+                              with the line of the file that drew the most in each.
      --doc                    Display information on how to write input files.");
 }
 
@@ -33,6 +90,13 @@ fn display_doc() {
 	println!("toimg: Takes as input a text file and converts it to a pixel image.
 The first line of the input file is a number that determines the width of the output image.
 The second line is the height.
+Either may be * to make the image grow along that axis to fit whatever is drawn, cropped to it.
+The pointer then never wraps along that axis, and commands that fill or transform the whole image use a size of 32.
+Lines of the form key: value may follow to set options for the file, with one of these keys:
+ background: #000 | Same as --background.
+ seed: 42         | Same as --seed.
+ time: 0.5        | Same as --time.
+ padding: 2       | Same as --padding.
 
 To repeat most commands, put a number before it (denoted n; default: 1):
  2r
@@ -66,6 +130,7 @@ Command    Description:
  m       | Puts n magenta pyxels.
  w       | Puts n white pyxels.
  .       | Puts n black pyxels.
+ x       | Erases n pyxels, showing the background (or the layers below) through them. Moves with the pen down erase too until the next color.
  ^       | Moves the pointer up n pyxels.
  v       | Moves the pointer down n pyxels.
  <       | Moves the pointer left n pyxels.
@@ -76,7 +141,150 @@ Command    Description:
  S       | Sets pointer to skip first n pyxels of every column or none if no number given.
  X       | Reverses the horizontal direction the pointer moves.
  Y       | Reverses the vertical direction the pointer moves.
- \\n      | Moves the pointer down and resets pointer x");
+ \\n      | Moves the pointer down and resets pointer x
+
+Directives are written as @name or @name(arg, ...). Numeric arguments may be mathematical expressions,
+and may use rand() for a random number from 0 to 1 or randint(a, b) for a random whole number from a to b.
+Axes are measured between pyxels, so the middle of a 32 pyxel wide image is 16.
+
+Directive                        Description:
+ @mirror(vertical[, x])          | Mirrors every put across a vertical axis at x (default: the middle).
+ @mirror(horizontal[, y])        | Mirrors every put across a horizontal axis at y (default: the middle).
+ @mirror(both[, x, y])           | Mirrors every put across both axes.
+ @mirror(radial[, n, x, y])      | Repeats every put n times (default: 4) around the point (x, y).
+ @mirror(none)                   | Stops mirroring.
+ @color(color)                   | Sets the color of lines and moves with the pen down without putting anything (ex: @color(#f80), @color(lr)).
+                                 | Color commands set it too.
+ @pen(down)                      | Makes ^, v, < and > paint every pyxel they leave, like a color command would.
+ @pen(up)                        | Makes moves stop painting.
+ @line(dx, dy)                   | Draws a line from the pointer to dx, dy pyxels away and moves the pointer to its end.
+ @path(dx, dy, dx, dy, ...)      | Draws lines through each point in turn, every one relative to the last.
+ @ellipse(rx[, ry][, fill])      | Draws an ellipse around the pointer, rx pyxels across and ry pyxels down from it (default: a circle).
+                                 | With fill, the inside is painted too.
+ @antialias(on)                  | Makes lines, paths and ellipses blend their edges by covering pyxels only partly.
+ @antialias(off)                 | Stops blending edges.
+ @pick(color, ...)               | Puts n pyxels, each a color picked at random from the list (ex: 5@pick(g, dg, lg)).
+ @scatter(n, color, ...)         | Puts n pyxels in random places inside the pointer's bounds, each a color picked at random from the list.
+ @noise(kind[, option, ...])     | Fills a region with noise shaded by a ramp of colors. Kind is value, perlin, simplex or voronoi.
+                                 | Options: scale=n sets how many pyxels across its features are (default: 8), octaves=n adds finer detail,
+                                 | seed=n picks a different noise (default: the --seed), region=x:y:w:h fills only part of the image,
+                                 | ramp=color:color:... blends between colors (default: .:w), palette=color:color:... only uses the colors given,
+                                 | tile makes the fill repeat seamlessly (ex: @noise(perlin, scale=4, octaves=3, ramp=db:b:y:g:dg, tile)).
+ @shader(r, g, b[, a][, region]) | Paints every pyxel of a region=x:y:w:h (default: the whole image) with the colors of expressions from 0 to 1.
+                                 | They may use x and y, u and v (from 0 to 1 across the region), w and h (the size of the region)
+                                 | and t (the --time) (ex: @shader(0.5+0.5*sin(x/3+t), 0.5+0.5*sin(y/3), u)).
+ @fd(n)                          | Moves the turtle n pyxels forward, the way it is heading. Lines it moves along wrap like any other move.
+                                 | With the pen down it paints every pyxel it leaves. The turtle is the pointer, and starts heading right.
+ @bk(n)                          | Moves the turtle n pyxels backward.
+ @lt(n)                          | Turns the turtle left (counterclockwise) by n degrees (default: 90).
+ @rt(n)                          | Turns the turtle right (clockwise) by n degrees (default: 90).
+ @heading(n)                     | Turns the turtle to face n degrees counterclockwise from right (ex: @heading(90) faces up).
+ @lsystem(axiom, rule, ...)      | Rewrites the axiom by rules like F=F+F-F, every symbol at once, iter=n times (default: 1).
+                                 | With mode=turtle (the default) the turtle then draws it: F and G move step=n pyxels forward
+                                 | (default: 2) painting, f moves without painting, + and - turn left and right by angle=n degrees
+                                 | (default: 90), | turns around, [ saves where the turtle is and ] goes back there.
+                                 | With mode=toimg it is run as commands instead. Growing past limit=n symbols (default: 100000) stops the run.
+                                 | (ex: @lsystem(F, F=F[+F]F[-F]F, iter=3, angle=25.7, step=2))
+ @push                           | Saves the whole pointer: where it is, its bounds (s, e, S, E), which way it moves (X, Y) and the turtle's heading.
+ @pop                            | Puts the pointer back how it was at the last @push, and forgets that save.
+ @goto(x, y)                     | Moves the pointer to x, y, counted from the top left of the image (ex: @goto(4, 2*3)).
+                                 | Points outside the pointer's bounds (s, e, S, E) are left alone with a warning, except along an axis that grows.
+ @mark(name)                     | Remembers where the pointer is under a name.
+ @jump(name)                     | Moves the pointer back to where it was marked with that name.
+ @advance(direction)             | Sets which way the pointer moves after each pyxel a color command or x puts (default: right).
+                                 | Direction is right, left, down, up, down-right, down-left, up-right or up-left (ex: 8r after @advance(down)).
+ @advance(dx, dy)                | Moves the pointer dx, dy pyxels after each put instead.
+ @edge(mode[, mode])             | Sets what the pointer does at the left and right edges of its bounds, and at the top and bottom
+                                 | (default: the same as at the sides). Mode is one of:
+                                 | wrap: moving the way it writes past the end goes on to the start of the next line (or column),
+                                 |       moving back past the start stops there (default at the sides);
+                                 | torus: goes on from the other edge (default at the top and bottom);
+                                 | clamp: stops at the edge; bounce: bounces off the edge and keeps moving the other way, like X or Y;
+                                 | stop: goes one past the edge, where puts are thrown away, until it moves back in;
+                                 | grow: goes on past the edge as far as it likes (the default along an axis of size *).
+ @traverse(order)                | Sets the order the pyxels a color command or x puts follow inside the pointer's bounds,
+                                 | and moves the pointer to the top left of them. Order is one of:
+                                 | row: left to right, then the next line down, as set by @advance and @edge (default);
+                                 | serpentine: like row, but every other line goes right to left; column: top to bottom, then the next column;
+                                 | spiral: clockwise around the edges and inward; hilbert: along a Hilbert curve.
+                                 | After the last pyxel the others come back to the first (ex: @traverse(spiral)20r4g).
+ @pointer(name, dx, dy[, X, Y])  | Makes another pointer called name, a copy of the main one dx, dy pyxels away from it.
+                                 | With X or Y it moves the other way across or down.
+ @select(name, ...)              | Makes commands run at every pointer named, in turn (ex: @select(main, left)).
+                                 | @select(all) selects every pointer, @select on its own just the main one.
+                                 | Moves, puts, lines, stamps and other commands that use the pointer run at each,
+                                 | and each keeps its own @push saves and @mark names. The rest run once, at the main pointer.
+ @brush(square[, n])             | Paints an n by n square (default: 1) wherever a color command, line or move with the pen down puts a pyxel.
+ @brush(round[, n])              | Paints a disc n pyxels across.
+ @brush(mask, rows)              | Paints a custom shape. Rows are separated by /, and anything but ., a space or 0 is painted (ex: @brush(mask, .x./xxx/.x.)).
+                                 | A stroke never paints the same pyxel twice, even where the brush overlaps itself.
+ @clip(rect, x, y, w, h)         | Makes puts from now on only land inside the rectangle.
+ @clip(ellipse, x, y, rx[, ry])  | Only inside the ellipse centred on (x, y).
+ @clip(color, color, ...)        | Only on pyxels of the current layer that are one of the colors (ex: @clip(color, r, #0f0)).
+ @clip(drawn)                    | Only on pyxels of the current layer that have something drawn on them.
+                                 | Colors and drawn look at the layer when @clip is run, so what is painted after doesn't change it.
+                                 | Adding outside to any of them makes puts land only outside the shape instead (ex: @clip(drawn, outside)).
+ @clip(off)                      | Lets puts land anywhere again.
+ @layer(name[, blend, opacity])  | Draws on the named layer from now on. Layers are created on top of the others the first time they are named.
+                                 | The first layer is called base. Blend is one of normal, multiply, screen, overlay, add, darken or lighten.
+                                 | Opacity goes from 0 to 1.
+ @stamp(path[, option, ...])     | Stamps an image file with its top left corner at the pointer. The path is relative to the input file's directory
+                                 | and can't be absolute or contain .. Options: scale=n scales it (nearest neighbour), crop=x:y:w:h stamps only part of it,
+                                 | key=color leaves out pixels of that color (ex: key=#f0f or key=m).
+ @flip(horizontal[, x, y, w, h]) | Swaps the left and right of what has been drawn in the region (default: the whole image).
+ @flip(vertical[, x, y, w, h])   | Swaps the top and bottom of what has been drawn in the region.
+ @rotate(n[, x, y, w, h])        | Rotates the region clockwise by n degrees (90, 180 or 270), keeping its top left corner in place.
+                                 | n must be a multiple of 90. The region keeps its shape, so pyxels turned out of it are lost.
+ @shift(dx, dy[, x, y, w, h])    | Moves the contents of the region. Pyxels moved out of it are lost.
+ @scroll(dx, dy[, x, y, w, h])   | Moves the contents of the region, wrapping around its edges.");
+}
+
+// Returns a layer name made safe to put in a file name, so it can't reach outside the output directory
+fn file_name(name: &str) -> String {
+	name.replace(['/', '\\'], "_").replace("..", "_")
+}
+
+// Prints why a file couldn't be saved and stops
+fn could_not_save(name: &str, e: std::io::Error) -> ! {
+	println!("toimg: Could not save {}: {}", name, e);
+	std::process::exit(1);
+}
+
+// Encodes the image in the given format and saves it as dir/name
+fn save(dir: &Path, name: &str, format: &str, vu8: &[u8], width: usize, height: usize, alpha: bool) -> std::io::Result<()> {
+	let colortype = if alpha {
+		image::ColorType::RGBA(8)
+	} else {
+		image::ColorType::RGB(8)
+	};
+	let mut path = dir.to_path_buf();
+	path.push(name);
+	let mut image = File::create(path)?;
+	match format {
+		".png"     => {
+			let encoder = image::png::PNGEncoder::new(&mut image);
+			encoder.encode(vu8, width as u32, height as u32, colortype)?;
+		},
+		".jpg"     => {
+			let mut encoder = image::jpeg::JPEGEncoder::new(&mut image);
+			encoder.encode(vu8, width as u32, height as u32, colortype)?;
+		},
+		".ico"     => {
+			let encoder = image::ico::ICOEncoder::new(&mut image);
+			encoder.encode(vu8, width as u32, height as u32, colortype)?;
+		},
+		".tif"     => {
+			let encoder = image::tiff::TiffEncoder::new(&mut image);
+			encoder.encode(vu8, width as u32, height as u32, colortype)
+				.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+		},
+		// .bmp, and anything else
+		_ => {
+			let mut encoder = image::bmp::BMPEncoder::new(&mut image);
+			encoder.encode(vu8, width as u32, height as u32, colortype)?;
+		},
+	};
+	image.sync_all()
 }
 
 fn main() -> std::io::Result<()> {
@@ -88,6 +296,29 @@ fn main() -> std::io::Result<()> {
 	let mut do_name = false;
 	let mut name = String::new();
 	let mut dark: bool = false;
+	let mut layers: bool = false;
+	let mut heatmap: bool = false;
+	let mut transparent: bool = false;
+	let mut do_background = false;
+	let mut background: Option<Background> = None;
+	let mut do_seed = false;
+	let mut seed: Option<u64> = None;
+	let mut do_time = false;
+	let mut time: Option<f64> = None;
+	let mut infinite: bool = false;
+	let mut do_padding = false;
+	let mut padding: Option<usize> = None;
+	let mut limits = Limits::new();
+	let mut do_max_commands = false;
+	let mut do_max_loops = false;
+	let mut do_max_pixels = false;
+	let mut do_timeout = false;
+	let mut trace: Option<Trace> = None;
+	let mut do_trace_line = false;
+	let mut do_trace_command = false;
+	let mut blame = false;
+	let mut blame_at: Vec<usize> = vec![];
+	let mut json = false;
 	let mut dir = PathBuf::new();
 	
 	for arg in env::args() {
@@ -96,7 +327,112 @@ fn main() -> std::io::Result<()> {
 			continue;
 		}
 		
-		if arg.starts_with("-") {
+		// An option that takes a value takes the next argument, even if it starts with -
+		if do_format || do_name || do_background || do_seed || do_time || do_padding
+			|| do_max_commands || do_max_loops || do_max_pixels || do_timeout || do_trace_line || do_trace_command {
+			let arg_clone = arg.clone();
+			if do_format {
+				do_format = false;
+				format = match arg_clone.to_ascii_lowercase().as_ref() {
+					"png"          => ".png".to_string(),
+					"bmp"          => ".bmp".to_string(),
+					"jpeg" | "jpg" => ".jpg".to_string(),
+					"icon" | "ico" => ".ico".to_string(),
+					"tiff" | "tif" => ".tif".to_string(),
+					"webp"         => {
+						println!("toimg: Can't save WebP images, only PNG, BMP, JPEG, ICO and TIFF");
+						println!("Try 'toimg --help' for more information.");
+						return Ok(());
+					}
+					_              => {
+						println!("toimg: Invalid format: {}", arg_clone);
+						println!("Try 'toimg --help' for more information.");
+						return Ok(());
+					}
+				}
+			}
+			if do_background {
+				do_background = false;
+				background = Background::from_name(&arg);
+				if background.is_none() {
+					println!("toimg: Invalid background: {}", arg);
+					println!("Try 'toimg --help' for more information.");
+					return Ok(());
+				}
+			}
+			if do_seed {
+				do_seed = false;
+				seed = arg.parse::<u64>().ok();
+				if seed.is_none() {
+					println!("toimg: Invalid seed: {}", arg);
+					println!("Try 'toimg --help' for more information.");
+					return Ok(());
+				}
+			}
+			if do_time {
+				do_time = false;
+				time = arg.parse::<f64>().ok();
+				if time.is_none() {
+					println!("toimg: Invalid time: {}", arg);
+					println!("Try 'toimg --help' for more information.");
+					return Ok(());
+				}
+			}
+			if do_padding {
+				do_padding = false;
+				padding = arg.parse::<usize>().ok();
+				if padding.is_none() {
+					println!("toimg: Invalid padding: {}", arg);
+					println!("Try 'toimg --help' for more information.");
+					return Ok(());
+				}
+			}
+			if do_max_commands || do_max_loops || do_max_pixels {
+				match arg.parse::<usize>() {
+					Ok(n) if do_max_commands => limits.set_commands(n),
+					Ok(n) if do_max_loops => limits.set_loops(n),
+					Ok(n) => limits.set_pixels(n),
+					Err(_) => {
+						println!("toimg: Invalid limit: {}", arg);
+						println!("Try 'toimg --help' for more information.");
+						return Ok(());
+					}
+				}
+				do_max_commands = false;
+				do_max_loops = false;
+				do_max_pixels = false;
+			}
+			if do_timeout {
+				do_timeout = false;
+				match arg.parse::<f64>() {
+					Ok(t) if t >= 0.0 => limits.set_time(t),
+					_ => {
+						println!("toimg: Invalid timeout: {}", arg);
+						println!("Try 'toimg --help' for more information.");
+						return Ok(());
+					}
+				}
+			}
+			if do_trace_line || do_trace_command {
+				let trace = trace.get_or_insert_with(Trace::new);
+				let set = if do_trace_line {
+					arg.parse::<usize>().ok().map(|n| trace.set_line(n))
+				} else {
+					parse_index(&arg).map(|n| trace.set_command(n))
+				};
+				if set.is_none() {
+					println!("toimg: Invalid trace filter: {}", arg);
+					println!("Try 'toimg --help' for more information.");
+					return Ok(());
+				}
+				do_trace_line = false;
+				do_trace_command = false;
+			}
+			if do_name {
+				do_name = false;
+				name = arg.clone();
+			}
+		} else if arg.starts_with("-") {
 			match arg.as_ref() {
 				"--help" | "-h" => {
 					display_help();
@@ -109,6 +445,24 @@ fn main() -> std::io::Result<()> {
 				"--format" | "-f" => do_format = true,
 				"--name" | "-n" => do_name = true,
 				"--dark" => dark = true,
+				"--layers" => layers = true,
+				"--heatmap" => heatmap = true,
+				"--transparent" => transparent = true,
+				"--background" | "-b" => do_background = true,
+				"--seed" => do_seed = true,
+				"--time" => do_time = true,
+				"--infinite" => infinite = true,
+				"--padding" => do_padding = true,
+				"--max-commands" => do_max_commands = true,
+				"--max-loops" => do_max_loops = true,
+				"--max-pixels" => do_max_pixels = true,
+				"--timeout" => do_timeout = true,
+				"--trace" => {
+					trace.get_or_insert_with(Trace::new);
+				}
+				"--trace-line" => do_trace_line = true,
+				"--trace-command" => do_trace_command = true,
+				"--json" => json = true,
 				_ => {
 					println!("toimg: Invalid option: {}", arg);
 					println!("Try 'toimg --help' for more information.");
@@ -131,42 +485,27 @@ fn main() -> std::io::Result<()> {
 			dir.push(sub);
 		} else if arg.starts_with("/") {
 			dir.push(arg);
-		} else {
-			if !do_format && !do_name {
-				dir.push(&curr_dir);
-				dir.push(arg);
-			} else {
-				let arg_clone = arg.clone();
-				if do_format {
-					do_format = false;
-					format = match arg_clone.to_ascii_lowercase().as_ref() {
-						"png"          => ".png".to_string(),
-						"bmp"          => ".bmp".to_string(),
-						"jpeg" | "jpg" => ".jpg".to_string(),
-						"icon" | "ico" => ".ico".to_string(),
-						"tiff" | "tif" => ".tif".to_string(),
-						_              => {
-							println!("toimg: Invalid format: {}", arg_clone);
-							println!("Try 'toimg --help' for more information.");
-							return Ok(());
-						}
-					}
-				}
-				let arg_clone = arg.clone();
-				if do_name {
-					name = arg_clone;
+		} else if !blame && dir.as_os_str().is_empty() && arg == "blame" {
+			blame = true;
+		} else if blame && !dir.as_os_str().is_empty() {
+			match arg.parse::<usize>() {
+				Ok(n) if blame_at.len() < 2 => blame_at.push(n),
+				_ => {
+					println!("toimg: Invalid position: {}", arg);
+					println!("Try 'toimg --help' for more information.");
+					return Ok(());
 				}
 			}
+		} else {
+			dir.push(&curr_dir);
+			dir.push(arg);
 		}
 	}
 	
-	if name.len() == 0 {
-		let mut dir_clone = dir.clone();
-		name = dir_clone.to_str().unwrap().split("/").into_iter().last().unwrap().to_string();
+	if name.is_empty() {
+		let dir_clone = dir.clone();
+		name = dir_clone.to_str().unwrap().split("/").last().unwrap().to_string();
 	}
-	name = format!("{}{}", name, format);
-	
-	let colortype = image::ColorType::RGB(8);
 	
 	let dir_clone = dir.clone();
 	match File::open(dir_clone) {
@@ -175,40 +514,88 @@ fn main() -> std::io::Result<()> {
 			let mut contents = String::new();
 			match buf_reader.read_to_string(&mut contents) {
 				Ok(_)	 => {
-					if let Some((w, h, cmds)) = parser::parse(&contents) {
+					if let Some((header, cmds)) = parser::parse(&contents) {
 						let mut pointer = pointer::Pointer::new();
-						pointer.set_width(w);
-						pointer.set_height(h);
-						let pyxels = cmd::run(&mut pointer, cmds);
-						let vu8 = pyxels.to_vec_u8(dark);
-						dir.pop();
-						dir.push(name);
-						let mut image = File::create(dir)?;
-						let width = pointer.width();
-						let height = pointer.height();
-						match format.as_ref() {
-							".png"     => {
-								let mut encoder = image::png::PNGEncoder::new(&mut image);
-								encoder.encode(&vu8, width as u32, height as u32, colortype);
-							},
-							".jpg"     => {
-								let mut encoder = image::jpeg::JPEGEncoder::new(&mut image);
-								encoder.encode(&vu8, width as u32, height as u32, colortype);
-							},
-							".ico"     => {
-								let mut encoder = image::ico::ICOEncoder::new(&mut image);
-								encoder.encode(&vu8, width as u32, height as u32, colortype);
-							},
-							".tif"     => {
-								let mut encoder = image::tiff::TiffEncoder::new(&mut image);
-								encoder.encode(&vu8, width as u32, height as u32, colortype);
-							},
-							".bmp" | _ => {
-								let mut encoder = image::bmp::BMPEncoder::new(&mut image);
-								encoder.encode(&vu8, width as u32, height as u32, colortype);
+						pointer.set_width(header.width());
+						pointer.set_height(header.height());
+						let mut settings = Settings::new();
+						let mut source_dir = dir.clone();
+						source_dir.pop();
+						settings.set_dir(source_dir);
+						if let Some(seed) = seed.or_else(|| header.get("seed").and_then(|s| s.parse::<u64>().ok())) {
+							settings.set_seed(seed);
+						}
+						if let Some(time) = time.or_else(|| header.get("time").and_then(|s| s.parse::<f64>().ok())) {
+							settings.set_time(time);
+						}
+						settings.set_infinite(infinite || header.infinite_x(), infinite || header.infinite_y());
+						if let Some(padding) = padding.or_else(|| header.get("padding").and_then(|s| s.parse::<usize>().ok())) {
+							settings.set_padding(padding);
+						}
+						settings.set_limits(limits);
+						settings.set_trace(trace.clone());
+						// A canvas too big to save isn't worth drawing
+						let check = limits.check_pixels(
+							if settings.infinite().0 { 0 } else { header.width() },
+							if settings.infinite().1 { 0 } else { header.height() },
+						);
+						let pyxels = match check.and_then(|_| cmd::run(&mut pointer, cmds, &settings)) {
+							Ok(pyxels) => pyxels,
+							Err(e) => {
+								println!("toimg: {}", e);
+								std::process::exit(1);
+							}
+						};
+						if blame {
+							match (json, blame_at.as_slice()) {
+								(true, &[x, y]) => println!("{}", blame::json(&pyxels, &contents, Some((x, y)))),
+								(true, []) => println!("{}", blame::json(&pyxels, &contents, None)),
+								(false, &[x, y]) => blame::print(&pyxels, &contents, x, y),
+								_ => {
+									println!("toimg: blame needs the x and y of a pixel, or --json");
+									println!("Try 'toimg --help' for more information.");
+								}
+							}
+							return Ok(());
+						}
+						// The command line wins over the header
+						let mut background = match background {
+							Some(b) => b,
+							None if dark => Background::Color([0, 0, 0, 255]),
+							None => match header.get("background").and_then(Background::from_name) {
+								Some(b) => b,
+								None => Background::Color([255, 255, 255, 255]),
 							},
 						};
-						image.sync_all()?;
+						if let (true, Background::Color(c)) = (transparent, background) {
+							background = Background::Color([c[0], c[1], c[2], 0]);
+						}
+						// JPEG has no alpha channel
+						let alpha = background.transparent() && format != ".jpg";
+						let vu8 = pyxels.to_vec_u8(&background, alpha);
+						let (_, _, width, height) = pyxels.area();
+						dir.pop();
+						let file = format!("{}{}", name, format);
+						if let Err(e) = save(&dir, &file, &format, &vu8, width, height, alpha) {
+							could_not_save(&file, e);
+						}
+						if layers {
+							for (i, layer) in pyxels.layers().iter().enumerate() {
+								let vu8 = pyxels.layer_to_vec_u8(i, &background, alpha);
+								let file = format!("{}-{}{}", name, file_name(layer.name()), format);
+								if let Err(e) = save(&dir, &file, &format, &vu8, width, height, alpha) {
+									could_not_save(&file, e);
+								}
+							}
+						}
+						if heatmap {
+							let heatmap = Heatmap::new(&pyxels);
+							let file = format!("{}-heatmap{}", name, format);
+							if let Err(e) = save(&dir, &file, &format, &heatmap.to_vec_u8(), width, height, false) {
+								could_not_save(&file, e);
+							}
+							heatmap.print_summary(&pyxels, HEATMAP_SQUARE, HEATMAP_SQUARES);
+						}
 					}
 				},
 				Err(e) => {
@@ -0,0 +1,128 @@
+use super::eval::*;
+use super::parser::parse_color;
+use super::pyxel::*;
+use std::collections::HashSet;
+
+// The shape a Mask lets puts into
+#[derive(Debug, Clone)]
+pub enum Shape {
+	Rect(isize, isize, isize, isize),
+	Ellipse(f64, f64, f64, f64),
+	Cells(HashSet<(isize, isize)>),
+}
+
+// Limits where puts land to the inside of a shape, or to the outside of it
+#[derive(Debug, Clone)]
+pub struct Mask {
+	shape: Shape,
+	outside: bool,
+}
+
+impl Mask {
+	// Builds a Mask from directive arguments, e.g. "rect", "2", "2", "8", "8".
+	// Colors and drawn look at the current layer as it is now, so later puts don't change the mask.
+	// Returns None for off, or if the arguments don't make a mask.
+	pub fn from_args(args: &[String], eval: &Eval, pyxels: &PyxelVec) -> Option<Self> {
+		let outside = args.iter().skip(1).any(|a| a.trim() == "outside");
+		let args: Vec<&str> = args.iter().map(|a| a.trim()).filter(|a| *a != "outside").collect();
+		let shape = match *args.first()? {
+			kind @ ("rect" | "ellipse") => {
+				let nums = eval.numbers("clip", args.iter().skip(1).copied())?;
+				match kind {
+					"rect" if nums.len() >= 4 => {
+						Shape::Rect(nums[0] as isize, nums[1] as isize, nums[2] as isize, nums[3] as isize)
+					}
+					"ellipse" if nums.len() >= 3 => Shape::Ellipse(nums[0], nums[1], nums[2], *nums.get(3).unwrap_or(&nums[2])),
+					_ => return None,
+				}
+			}
+			"color" => {
+				let colors: Vec<[u8; 4]> = args.iter().skip(1).filter_map(|a| parse_color(a)).collect();
+				Shape::Cells(cells(pyxels, |c| colors.iter().any(|k| k[..3] == c[..3])))
+			}
+			"drawn" => Shape::Cells(cells(pyxels, |_| true)),
+			_ => return None,
+		};
+		Some(Mask { shape, outside })
+	}
+
+	// Returns whether a put at (x, y) may land
+	pub fn allows(&self, x: isize, y: isize) -> bool {
+		let inside = match &self.shape {
+			Shape::Rect(rx, ry, w, h) => x >= *rx && y >= *ry && x < rx + w && y < ry + h,
+			Shape::Ellipse(cx, cy, rx, ry) => {
+				let dx = (x as f64 - cx) / rx.abs().max(0.5);
+				let dy = (y as f64 - cy) / ry.abs().max(0.5);
+				dx * dx + dy * dy <= 1.0
+			}
+			Shape::Cells(cells) => cells.contains(&(x, y)),
+		};
+		inside != self.outside
+	}
+}
+
+// Returns every position on the current layer that has something drawn on it matching the test
+fn cells<F>(pyxels: &PyxelVec, test: F) -> HashSet<(isize, isize)>
+where
+	F: Fn([u8; 4]) -> bool,
+{
+	let (x, y, w, h) = pyxels.area();
+	let mut cells = HashSet::new();
+	for (i, color) in pyxels.flatten_region(x, y, w, h).iter().enumerate() {
+		let c = [
+			(color[0] * 255.0).round() as u8,
+			(color[1] * 255.0).round() as u8,
+			(color[2] * 255.0).round() as u8,
+			(color[3] * 255.0).round() as u8,
+		];
+		if c[3] > 0 && test(c) {
+			cells.insert((x + (i % w) as isize, y + (i / w) as isize));
+		}
+	}
+	cells
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn args(s: &[&str]) -> Vec<String> {
+		s.iter().map(|a| a.to_string()).collect()
+	}
+
+	#[test]
+	fn rect_and_outside() {
+		let pyxels = PyxelVec::new(8, 8);
+		let mask = Mask::from_args(&args(&["rect", "2", "2", "3", "3"]), &Eval::new(0), &pyxels).unwrap();
+		assert!(mask.allows(2, 2) && mask.allows(4, 4));
+		assert!(!mask.allows(5, 4) && !mask.allows(1, 2));
+		let mask = Mask::from_args(&args(&["rect", "outside", "2", "2", "3", "3"]), &Eval::new(0), &pyxels).unwrap();
+		assert!(!mask.allows(2, 2) && mask.allows(5, 4));
+	}
+
+	#[test]
+	fn bad_numbers_make_no_mask() {
+		let pyxels = PyxelVec::new(8, 8);
+		assert!(Mask::from_args(&args(&["rect", "2", "foo", "8", "8"]), &Eval::new(0), &pyxels).is_none());
+		assert!(Mask::from_args(&args(&["ellipse", "4", "4", "x"]), &Eval::new(0), &pyxels).is_none());
+	}
+
+	#[test]
+	fn ellipse() {
+		let pyxels = PyxelVec::new(8, 8);
+		let mask = Mask::from_args(&args(&["ellipse", "4", "4", "2", "1"]), &Eval::new(0), &pyxels).unwrap();
+		assert!(mask.allows(4, 4) && mask.allows(6, 4) && mask.allows(4, 5));
+		assert!(!mask.allows(4, 6) && !mask.allows(6, 5));
+	}
+
+	#[test]
+	fn colors_and_drawn_look_at_the_layer() {
+		let mut pyxels = PyxelVec::new(4, 1);
+		pyxels.push(Pyxel::new([255, 0, 0, 255], 0, 0));
+		pyxels.push(Pyxel::new([0, 255, 0, 255], 1, 0));
+		let mask = Mask::from_args(&args(&["color", "g"]), &Eval::new(0), &pyxels).unwrap();
+		assert!(!mask.allows(0, 0) && mask.allows(1, 0) && !mask.allows(2, 0));
+		let mask = Mask::from_args(&args(&["drawn", "outside"]), &Eval::new(0), &pyxels).unwrap();
+		assert!(!mask.allows(0, 0) && !mask.allows(1, 0) && mask.allows(2, 0));
+	}
+}
@@ -0,0 +1,373 @@
+use super::eval::*;
+use super::limits::*;
+use super::parser::parse_color;
+use super::random::*;
+use super::transform::region;
+
+// The kinds of noise a fill can use
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Noise {
+	Value,
+	Perlin,
+	Simplex,
+	Voronoi,
+}
+
+impl Noise {
+	// Returns the Noise called name, if there is one
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"value" => Some(Noise::Value),
+			"perlin" => Some(Noise::Perlin),
+			"simplex" => Some(Noise::Simplex),
+			"voronoi" | "cells" => Some(Noise::Voronoi),
+			_ => None,
+		}
+	}
+
+	// Returns the noise at (x, y), from 0 to 1. Lattice cells are one unit across.
+	// With a period the noise repeats every pw by ph units, which must then be whole numbers for the lattice kinds.
+	fn sample(&self, x: f64, y: f64, period: Option<(f64, f64)>, seed: u64) -> f64 {
+		match self {
+			Noise::Value => value(x, y, period, seed),
+			Noise::Perlin => (perlin(x, y, period, seed) * std::f64::consts::SQRT_2 + 1.0) / 2.0,
+			Noise::Simplex => match period {
+				// The simplex lattice is skewed, so it can't simply wrap. Blend four shifted copies instead.
+				Some((pw, ph)) => {
+					let (x, y) = (x.rem_euclid(pw), y.rem_euclid(ph));
+					let (fx, fy) = (x / pw, y / ph);
+					let n = simplex(x, y, seed) * (1.0 - fx) * (1.0 - fy)
+						+ simplex(x - pw, y, seed) * fx * (1.0 - fy)
+						+ simplex(x, y - ph, seed) * (1.0 - fx) * fy
+						+ simplex(x - pw, y - ph, seed) * fx * fy;
+					// Blending flattens the noise, most of all in the middle
+					let spread = ((1.0 - fx).powi(2) + fx * fx).sqrt() * ((1.0 - fy).powi(2) + fy * fy).sqrt();
+					(n / spread + 1.0) / 2.0
+				}
+				None => (simplex(x, y, seed) + 1.0) / 2.0,
+			},
+			Noise::Voronoi => voronoi(x, y, period, seed),
+		}
+		.clamp(0.0, 1.0)
+	}
+}
+
+// A region filled with noise, mapped to colors
+#[derive(Debug, Clone)]
+pub struct NoiseFill {
+	noise: Noise,
+	scale: f64,
+	octaves: usize,
+	seed: u64,
+	colors: Vec<[u8; 4]>,
+	banded: bool,
+	region: Vec<f64>,
+	tile: bool,
+}
+
+impl NoiseFill {
+	// Returns a new NoiseFill of the whole canvas, shaded from black to white
+	pub fn new(noise: Noise, seed: u64) -> Self {
+		NoiseFill {
+			noise,
+			scale: 8.0,
+			octaves: 1,
+			seed,
+			colors: vec![[0, 0, 0, 255], [255, 255, 255, 255]],
+			banded: false,
+			region: vec![],
+			tile: false,
+		}
+	}
+
+	// Builds a NoiseFill from directive arguments: the kind of noise followed by the options
+	// scale=n, octaves=n, seed=n, ramp=color:color:..., palette=color:color:..., region=x:y:w:h and tile
+	pub fn from_args(args: &[String], eval: &Eval, seed: u64) -> Option<Self> {
+		let mut fill = NoiseFill::new(Noise::from_name(args.first()?)?, seed);
+		for arg in args.iter().skip(1) {
+			if arg == "tile" {
+				fill.set_tile(true);
+				continue;
+			}
+			let (key, value) = match arg.find('=') {
+				Some(i) => (arg[..i].trim(), arg[i + 1..].trim()),
+				None => continue,
+			};
+			match key {
+				"scale" => {
+					if let Some(n) = eval.eval(value) {
+						fill.set_scale(n);
+					}
+				}
+				"octaves" => {
+					if let Some(n) = eval.eval(value) {
+						fill.set_octaves(n.max(1.0) as usize);
+					}
+				}
+				"seed" => {
+					if let Some(n) = eval.eval(value) {
+						fill.set_seed(n as i64 as u64);
+					}
+				}
+				"ramp" | "palette" => {
+					let colors: Vec<[u8; 4]> = value.split(':').filter_map(parse_color).collect();
+					if !colors.is_empty() {
+						fill.set_colors(colors, key == "palette");
+					}
+				}
+				"region" => {
+					fill.set_region(eval.numbers("noise", value.split(':'))?);
+				}
+				_ => (),
+			}
+		}
+		Some(fill)
+	}
+
+	// Set respective values
+	pub fn set_scale(&mut self, scale: f64) {
+		if scale > 0.0 {
+			self.scale = scale;
+		}
+	}
+
+	pub fn set_octaves(&mut self, octaves: usize) {
+		self.octaves = octaves.min(16);
+	}
+
+	pub fn set_seed(&mut self, seed: u64) {
+		self.seed = seed;
+	}
+
+	// A ramp blends smoothly between its colors, a palette only uses the colors themselves
+	pub fn set_colors(&mut self, colors: Vec<[u8; 4]>, banded: bool) {
+		self.colors = colors;
+		self.banded = banded;
+	}
+
+	// Takes x, y, w and h
+	pub fn set_region(&mut self, region: Vec<f64>) {
+		self.region = region;
+	}
+
+	// Makes the noise repeat seamlessly across the region
+	pub fn set_tile(&mut self, tile: bool) {
+		self.tile = tile;
+	}
+
+	// Returns every pyxel of the region as (x, y, color) on a canvas of the given size.
+	// Every row is charged to the budget before it is worked out.
+	pub fn pyxels(
+		&self,
+		width: usize,
+		height: usize,
+		budget: &mut Budget,
+	) -> Result<Vec<(isize, isize, [u8; 4])>, LimitError> {
+		let (rx, ry, rw, rh) = region(&self.region, width, height);
+		// When tiling, the scale is bent a little so a whole number of cells fits the region
+		let cells = (
+			(rw as f64 / self.scale).round().max(1.0),
+			(rh as f64 / self.scale).round().max(1.0),
+		);
+
+		let mut v = vec![];
+		for y in ry..ry + rh {
+			budget.charge(rw)?;
+			for x in rx..rx + rw {
+				// Sampled at the middle of each pyxel, as lattice points are flat for Perlin and simplex noise
+				let (nx, ny) = if self.tile {
+					(((x - rx) as f64 + 0.5) / rw as f64 * cells.0, ((y - ry) as f64 + 0.5) / rh as f64 * cells.1)
+				} else {
+					((x as f64 + 0.5) / self.scale, (y as f64 + 0.5) / self.scale)
+				};
+				let t = self.octaves(nx, ny, if self.tile { Some(cells) } else { None });
+				v.push((x as isize, y as isize, self.color(t)));
+			}
+		}
+		Ok(v)
+	}
+
+	// Adds up the octaves, each twice as fine and half as strong as the last
+	fn octaves(&self, x: f64, y: f64, period: Option<(f64, f64)>) -> f64 {
+		let mut sum = 0.0;
+		let mut total = 0.0;
+		let mut amplitude = 1.0;
+		let mut frequency = 1.0;
+		for octave in 0..self.octaves {
+			let period = period.map(|(pw, ph)| (pw * frequency, ph * frequency));
+			let seed = self.seed.wrapping_add(octave as u64);
+			sum += self.noise.sample(x * frequency, y * frequency, period, seed) * amplitude;
+			total += amplitude;
+			amplitude /= 2.0;
+			frequency *= 2.0;
+		}
+		sum / total
+	}
+
+	// Maps a noise value from 0 to 1 to a color
+	fn color(&self, t: f64) -> [u8; 4] {
+		let n = self.colors.len();
+		if self.banded || n == 1 {
+			return self.colors[((t * n as f64) as usize).min(n - 1)];
+		}
+		let f = t * (n - 1) as f64;
+		let i = (f as usize).min(n - 2);
+		let f = f - i as f64;
+		let (a, b) = (self.colors[i], self.colors[i + 1]);
+		let mut c = [0; 4];
+		for k in 0..4 {
+			c[k] = (a[k] as f64 + (b[k] as f64 - a[k] as f64) * f).round() as u8;
+		}
+		c
+	}
+}
+
+// Returns a random number for the lattice point (x, y), wrapped by the period if there is one
+fn hash(x: i64, y: i64, period: Option<(f64, f64)>, seed: u64) -> u64 {
+	let (x, y) = match period {
+		Some((pw, ph)) => (x.rem_euclid(pw as i64), y.rem_euclid(ph as i64)),
+		None => (x, y),
+	};
+	let mut random = Random::new(
+		seed ^ (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ (y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f),
+	);
+	random.next_u64()
+}
+
+// Returns a number from 0 to 1 for the lattice point (x, y)
+fn hash_f64(x: i64, y: i64, period: Option<(f64, f64)>, seed: u64) -> f64 {
+	(hash(x, y, period, seed) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Eases t so cells join without creases
+fn fade(t: f64) -> f64 {
+	t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+	a + (b - a) * t
+}
+
+// Random values at the lattice points, blended between them. From 0 to 1.
+fn value(x: f64, y: f64, period: Option<(f64, f64)>, seed: u64) -> f64 {
+	let (ix, iy) = (x.floor() as i64, y.floor() as i64);
+	let (fx, fy) = (fade(x - x.floor()), fade(y - y.floor()));
+	let top = lerp(hash_f64(ix, iy, period, seed), hash_f64(ix + 1, iy, period, seed), fx);
+	let bottom = lerp(hash_f64(ix, iy + 1, period, seed), hash_f64(ix + 1, iy + 1, period, seed), fx);
+	lerp(top, bottom, fy)
+}
+
+// Returns one of eight unit gradients for the lattice point (x, y)
+fn gradient(x: i64, y: i64, period: Option<(f64, f64)>, seed: u64) -> (f64, f64) {
+	let angle = (hash(x, y, period, seed) % 8) as f64 * std::f64::consts::FRAC_PI_4;
+	(angle.cos(), angle.sin())
+}
+
+// Perlin's gradient noise. From about -0.7 to 0.7.
+fn perlin(x: f64, y: f64, period: Option<(f64, f64)>, seed: u64) -> f64 {
+	let (ix, iy) = (x.floor() as i64, y.floor() as i64);
+	let (dx, dy) = (x - x.floor(), y - y.floor());
+	let dot = |cx: i64, cy: i64| {
+		let (gx, gy) = gradient(ix + cx, iy + cy, period, seed);
+		gx * (dx - cx as f64) + gy * (dy - cy as f64)
+	};
+	let (fx, fy) = (fade(dx), fade(dy));
+	lerp(lerp(dot(0, 0), dot(1, 0), fx), lerp(dot(0, 1), dot(1, 1), fx), fy)
+}
+
+// Simplex noise on a triangular lattice. From about -1 to 1.
+fn simplex(x: f64, y: f64, seed: u64) -> f64 {
+	let f2 = 0.5 * (3f64.sqrt() - 1.0);
+	let g2 = (3.0 - 3f64.sqrt()) / 6.0;
+	let s = (x + y) * f2;
+	let (i, j) = ((x + s).floor(), (y + s).floor());
+	let t = (i + j) * g2;
+	let (x0, y0) = (x - (i - t), y - (j - t));
+	let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+	let corners = [
+		(0, 0, x0, y0),
+		(i1, j1, x0 - i1 as f64 + g2, y0 - j1 as f64 + g2),
+		(1, 1, x0 - 1.0 + 2.0 * g2, y0 - 1.0 + 2.0 * g2),
+	];
+	let mut n = 0.0;
+	for (ci, cj, cx, cy) in corners.iter() {
+		let t = 0.5 - cx * cx - cy * cy;
+		if t > 0.0 {
+			let (gx, gy) = gradient(i as i64 + ci, j as i64 + cj, None, seed);
+			n += t.powi(4) * (gx * cx + gy * cy);
+		}
+	}
+	70.0 * n
+}
+
+// The distance to the nearest of one random point per cell. From 0 to 1.
+fn voronoi(x: f64, y: f64, period: Option<(f64, f64)>, seed: u64) -> f64 {
+	let (ix, iy) = (x.floor() as i64, y.floor() as i64);
+	let mut nearest = f64::MAX;
+	for cy in iy - 1..=iy + 1 {
+		for cx in ix - 1..=ix + 1 {
+			let h = hash(cx, cy, period, seed);
+			let px = cx as f64 + (h & 0xffff) as f64 / 65536.0;
+			let py = cy as f64 + ((h >> 16) & 0xffff) as f64 / 65536.0;
+			nearest = nearest.min(((px - x).powi(2) + (py - y).powi(2)).sqrt());
+		}
+	}
+	nearest
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const RED: [u8; 4] = [255, 0, 0, 255];
+	const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+	fn fill(fill: &NoiseFill, width: usize, height: usize) -> Vec<(isize, isize, [u8; 4])> {
+		fill.pyxels(width, height, &mut Budget::new(Limits::new())).unwrap()
+	}
+
+	#[test]
+	fn fills_only_the_region() {
+		let mut noise = NoiseFill::new(Noise::Value, 1);
+		noise.set_region(vec![2.0, 1.0, 3.0, 2.0]);
+		let positions: Vec<(isize, isize)> = fill(&noise, 8, 8).iter().map(|p| (p.0, p.1)).collect();
+		assert_eq!(positions, [(2, 1), (3, 1), (4, 1), (2, 2), (3, 2), (4, 2)]);
+	}
+
+	#[test]
+	fn palettes_only_use_their_colors() {
+		let mut noise = NoiseFill::new(Noise::Perlin, 1);
+		noise.set_scale(2.0);
+		noise.set_colors(vec![RED, BLUE], true);
+		let pyxels = fill(&noise, 16, 16);
+		assert!(pyxels.iter().all(|p| p.2 == RED || p.2 == BLUE));
+		assert!(pyxels.iter().any(|p| p.2 == RED) && pyxels.iter().any(|p| p.2 == BLUE));
+	}
+
+	#[test]
+	fn ramps_blend_between_their_colors() {
+		let noise = NoiseFill::new(Noise::Value, 1);
+		assert_eq!(noise.color(0.0), [0, 0, 0, 255]);
+		assert_eq!(noise.color(0.5), [128, 128, 128, 255]);
+		assert_eq!(noise.color(1.0), [255, 255, 255, 255]);
+	}
+
+	#[test]
+	fn the_seed_picks_the_noise() {
+		let a = fill(&NoiseFill::new(Noise::Simplex, 1), 16, 16);
+		assert_eq!(a, fill(&NoiseFill::new(Noise::Simplex, 1), 16, 16));
+		assert_ne!(a, fill(&NoiseFill::new(Noise::Simplex, 2), 16, 16));
+	}
+
+	#[test]
+	fn tiled_noise_repeats() {
+		for &kind in [Noise::Value, Noise::Perlin, Noise::Voronoi].iter() {
+			let mut noise = NoiseFill::new(kind, 3);
+			noise.set_octaves(3);
+			let period = Some((4.0, 4.0));
+			for &(x, y) in [(0.3, 0.7), (1.5, 2.25)].iter() {
+				assert!((noise.octaves(x, y, period) - noise.octaves(x + 4.0, y + 4.0, period)).abs() < 1e-9);
+			}
+		}
+	}
+}
@@ -1,11 +1,52 @@
 use super::cmd::*;
-use meval;
+use std::iter::Peekable;
 
-pub fn parse(s: &String) -> Option<(usize, usize, Vec<Command>)> {
+pub fn parse(s: &str) -> Option<(Header, Vec<Command>)> {
 	let mut parser = Parser::new();
 	parser.parse(s)
 }
 
+// Converts commands written without a header, like those an L-system expands to
+pub fn parse_commands(s: &str) -> Vec<Command> {
+	let mut parser = Parser::new();
+	parser.read(s)
+}
+
+// The first lines of a source: the width, the height and any key: value lines right after them.
+// A width or height of * makes the canvas grow along that axis.
+#[derive(Debug, Clone)]
+pub struct Header {
+	width: usize,
+	height: usize,
+	infinite_x: bool,
+	infinite_y: bool,
+	keys: Vec<(String, String)>,
+}
+
+impl Header {
+	// Returns respective values
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	pub fn infinite_x(&self) -> bool {
+		self.infinite_x
+	}
+
+	pub fn infinite_y(&self) -> bool {
+		self.infinite_y
+	}
+
+	// Returns the value of a key, if the header has it
+	pub fn get(&self, key: &str) -> Option<&str> {
+		self.keys.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+	}
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum Tone {
 	Light,
@@ -29,6 +70,10 @@ struct Parser {
 	tone: Tone,
 	comment: Comment,
 	cmds: Vec<Command>,
+	start: Option<usize>,
+	first_line: usize,
+	source: String,
+	positions: Vec<(usize, usize)>,
 }
 
 impl Parser {
@@ -41,6 +86,10 @@ impl Parser {
 			tone: Tone::Normal,
 			comment: Comment::Nope,
 			cmds: vec![],
+			start: None,
+			first_line: 1,
+			source: String::new(),
+			positions: vec![],
 		}
 	}
 
@@ -80,6 +129,7 @@ impl Parser {
 		self.num = String::new();
 		self.sharps = false;
 		self.tone = Tone::Normal;
+		self.start = None;
 	}
 
 	// Set respective values
@@ -91,10 +141,6 @@ impl Parser {
 		self.hex = a;
 	}
 
-	fn set_num(&mut self, a: String) {
-		self.num = a;
-	}
-
 	fn push_hex(&mut self, a: char) {
 		self.hex.push(a);
 	}
@@ -115,50 +161,74 @@ impl Parser {
 		self.comment = a;
 	}
 
-	// Add a command into the list of commands
-	fn put(&mut self) {
+	// Remembers where the command being read starts, if it hasn't already
+	fn mark(&mut self, at: usize) {
+		if self.start.is_none() {
+			self.start = Some(at);
+		}
+	}
+
+	// Add a command into the list of commands. Takes where in the source it ends.
+	fn put(&mut self, end: usize) {
 		if self.cmd() != char::from(0u8) {
 			let int = parse_num(self.num()).0;
 			let rep = parse_num(self.num()).1;
 			if self.sharps {
-				self.cmds.push(Command::new(
+				self.push(Command::new(
 					self.cmd(),
 					parse_hex(self.hex()),
 					int,
 					rep,
-					self.hex().len() == 0 && self.num().len() == 0,
-				));
+					self.hex().is_empty() && self.num().is_empty(),
+				), end);
 			} else {
-				self.cmds.push(Command::new(
+				self.push(Command::new(
 					self.cmd(),
 					tone(self.hex(), self.tone()),
 					int,
 					rep,
-					self.hex().len() == 0 && self.num().len() == 0,
-				));
+					self.hex().is_empty() && self.num().is_empty(),
+				), end);
 			}
 		}
 	}
 
+	// Add a directive into the list of commands. The number before it is used like any other command's.
+	fn put_directive(&mut self, name: String, args: Vec<String>, end: usize) {
+		let int = parse_num(self.num()).0;
+		let rep = parse_num(self.num()).1;
+		self.push(Command::directive(name, args, int, rep, self.num().is_empty()), end);
+	}
+
+	// Adds a command, noting where it was written: from the marked start up to end
+	fn push(&mut self, mut cmd: Command, end: usize) {
+		let start = self.start.unwrap_or(end).min(end);
+		let last = if end > start { end - 1 } else { start };
+		if let (Some(&(line, column)), Some(&(end_line, end_column))) = (self.positions.get(start), self.positions.get(last)) {
+			cmd.set_span(Span::new(line, column, end_line, end_column));
+			cmd.set_text(self.source[start..end].trim_end().to_string());
+		}
+		self.cmds.push(cmd);
+	}
+
 	// Takes a string and converts it to commands
-	fn parse(&mut self, s: &String) -> Option<(usize, usize, Vec<Command>)> {
-		let mut width = 32usize;
-		let mut height = 32usize;
+	fn parse(&mut self, s: &str) -> Option<(Header, Vec<Command>)> {
 		let mut lines: Vec<&str> = s.lines().collect();
-		if lines.len() > 1 {
-			width = match lines[0].parse::<usize>() {
-				Ok(n) => n,
-				Err(e) => 32,
-			};
-			height = match lines[1].parse::<usize>() {
-				Ok(n) => n,
-				Err(e) => 32,
-			};
-		} else {
+		if lines.len() < 2 {
 			return None;
 		}
+		let width = lines[0].parse::<usize>().unwrap_or(32);
+		let height = lines[1].parse::<usize>().unwrap_or(32);
+		let infinite_x = lines[0].trim() == "*";
+		let infinite_y = lines[1].trim() == "*";
 		lines.remove(0);
 		lines.remove(0);
+		let mut keys = vec![];
+		while let Some(key) = lines.first().and_then(|l| header_key(l)) {
+			keys.push(key);
+			lines.remove(0);
+		}
+		self.first_line = 3 + keys.len();
 		let mut s = String::new();
 		for line in lines {
 			for c in line.chars() {
@@ -166,16 +236,34 @@ impl Parser {
 			}
 			s.push('\n');
 		}
-		
-		
+		Some((Header { width, height, infinite_x, infinite_y, keys }, self.read(&s)))
+	}
+
+	// Converts the body of a source, everything after the header, to commands
+	fn read(&mut self, s: &str) -> Vec<Command> {
 		self.cmds = vec![];
 		self.reset();
+		// The line and column of every byte, for the commands' spans
+		self.source = s.to_string();
+		self.positions = Vec::with_capacity(s.len());
+		let (mut line, mut column) = (self.first_line, 1);
+		for c in s.chars() {
+			for _ in 0..c.len_utf8() {
+				self.positions.push((line, column));
+			}
+			if c == '\n' {
+				line += 1;
+				column = 1;
+			} else {
+				column += 1;
+			}
+		}
 		let num_list: String = String::from("0123456789-+/*");
 		let hex_list: String = String::from("0123456789abcdef");
 		let color_list: String = String::from("rgbcymw.");
-		let control_list = String::from("^v<>[]esESXY");
-		let mut chars = s.chars();
-		while let Some(c) = chars.next() {
+		let control_list = String::from("^v<>[]esESXYx");
+		let mut chars = s.char_indices().peekable();
+		while let Some((at, c)) = chars.next() {
 			match c {
 				'|' => {
 					if self.comment() == Comment::Nope {
@@ -203,8 +291,9 @@ impl Parser {
 
 				'\n' => {
 					if self.comment() == Comment::Nope {
+						self.mark(at);
 						self.set_cmd('n');
-						self.put();
+						self.put(at + 1);
 					} else {
 						if self.comment() == Comment::Line {
 							self.set_comment(Comment::Nope);
@@ -218,49 +307,117 @@ impl Parser {
 
 			if self.comment == Comment::Nope {
 				if self.sharps() {
-					if let Some(_) = hex_list.find(c) {
+					if hex_list.find(c).is_some() {
 						self.push_hex(c);
 						continue;
 					}
 				} else {
-					if let Some(_) = num_list.find(c) {
+					if num_list.find(c).is_some() {
+						self.mark(at);
 						self.push_num(c);
 						continue;
 					}
 				}
 				match c {
 					'l' => {
+						self.mark(at);
 						self.set_tone(Tone::Light);
 					}
 					'd' => {
+						self.mark(at);
 						self.set_tone(Tone::Dark);
 					}
 					'#' => {
+						self.mark(at);
 						self.set_cmd('#');
 						self.set_sharps(true);
 					}
+					'@' => {
+						self.mark(at);
+						let (name, args) = read_directive(&mut chars);
+						let end = chars.peek().map_or(s.len(), |&(i, _)| i);
+						self.put_directive(name, args, end);
+						self.reset();
+					}
 					_ => (),
 				}
-				if let Some(_) = color_list.find(c) {
+				if color_list.find(c).is_some() {
+					self.mark(at);
 					self.set_cmd('#');
 					self.set_hex(c.to_string());
-					self.put();
+					self.put(at + c.len_utf8());
 					self.reset();
 				}
-				if let Some(_) = control_list.find(c) {
+				if control_list.find(c).is_some() {
+					self.mark(at);
 					self.set_cmd(c);
-					self.put();
+					self.put(at + c.len_utf8());
 					self.reset();
 				}
 			}
 		}
 
-		self.put();
+		self.put(s.len());
 		self.reset();
-		Some((width, height, self.cmds()))
+		self.cmds()
 	}
 }
 
+// The keys a header line may set
+const HEADER_KEYS: [&str; 4] = ["background", "seed", "time", "padding"];
+
+// Splits a header line like "background: #000" into its key and value.
+// Anything else is the first line of commands.
+fn header_key(line: &str) -> Option<(String, String)> {
+	let i = line.find(':')?;
+	let key = line[..i].trim();
+	if HEADER_KEYS.contains(&key) {
+		Some((key.to_string(), line[i + 1..].trim().to_string()))
+	} else {
+		None
+	}
+}
+
+// Reads the name and arguments of a directive following an @, e.g. mirror(vertical, 16).
+// Arguments are split on commas that are not nested inside parentheses.
+fn read_directive<I: Iterator<Item = (usize, char)>>(chars: &mut Peekable<I>) -> (String, Vec<String>) {
+	let mut name = String::new();
+	while let Some(&(_, c)) = chars.peek() {
+		if c.is_ascii_alphanumeric() || c == '_' {
+			name.push(c);
+			chars.next();
+		} else {
+			break;
+		}
+	}
+
+	let mut args = vec![];
+	if chars.peek().map(|&(_, c)| c) == Some('(') {
+		chars.next();
+		let mut depth = 0;
+		let mut arg = String::new();
+		for (_, c) in chars {
+			match c {
+				'(' => depth += 1,
+				')' if depth == 0 => break,
+				')' => depth -= 1,
+				',' if depth == 0 => {
+					args.push(arg.trim().to_string());
+					arg = String::new();
+					continue;
+				}
+				_ => (),
+			}
+			arg.push(c);
+		}
+		if !arg.trim().is_empty() || !args.is_empty() {
+			args.push(arg.trim().to_string());
+		}
+	}
+
+	(name, args)
+}
+
 // Convert a string containing a valid hex value to an array of u8
 fn parse_hex(s: String) -> [u8; 4] {
 	let mut r: u8 = 255;
@@ -330,17 +487,38 @@ fn parse_hex(s: String) -> [u8; 4] {
 fn parse_num(s: String) -> (isize, usize) {
 	let mut i: isize = 0;
 	let mut u: usize = 1;
-	match meval::eval_str(s) {
-		Ok(n) => {
-			i = n as isize;
-			u = n.abs() as usize;
-		}
-		_ => (),
+	if let Ok(n) = meval::eval_str(s) {
+		i = n as isize;
+		u = n.abs() as usize;
 	}
 
 	(i, u)
 }
 
+// Converts a color as written in a directive argument to an array of u8.
+// Takes a hex code (#f00, #ff000088) or a color command with an optional tone (r, lr, db, .).
+pub fn parse_color(s: &str) -> Option<[u8; 4]> {
+	let s = s.trim();
+	if let Some(hex) = s.strip_prefix('#') {
+		let hex = hex.to_ascii_lowercase();
+		if [3, 4, 6, 8].contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+			return Some(parse_hex(hex));
+		}
+		return None;
+	}
+	let (t, c) = match s.len() {
+		1 => (Tone::Normal, s),
+		2 if s.starts_with('l') => (Tone::Light, &s[1..]),
+		2 if s.starts_with('d') => (Tone::Dark, &s[1..]),
+		_ => return None,
+	};
+	if "rgbcymw.".contains(c) {
+		Some(tone(c.to_string(), t))
+	} else {
+		None
+	}
+}
+
 // Makes a color darker or lighter or does nothing to it
 fn tone(s: String, l: Tone) -> [u8; 4] {
 	let mut max: u8 = 255;
@@ -367,3 +545,28 @@ fn tone(s: String, l: Tone) -> [u8; 4] {
 		_ => [min, min, min, 255],
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn header_keys() {
+		let (header, comms) = parse("4\n3\nseed: 7\nbackground: #000\nr").unwrap();
+		assert_eq!(header.get("seed"), Some("7"));
+		assert_eq!(header.get("background"), Some("#000"));
+		assert_eq!(comms.iter().filter(|c| *c.verb() == '#').count(), 1);
+	}
+
+	#[test]
+	fn only_known_header_keys() {
+		let (header, comms) = parse("4\n3\nrr: bb\ng").unwrap();
+		assert_eq!(header.get("rr"), None);
+		let colors: Vec<u8> = comms.iter().filter(|c| *c.verb() == '#').map(|c| c.hex()[0]).collect();
+		assert_eq!(colors.len(), 5);
+		// The line after a key that isn't one is still commands
+		let (header, comms) = parse("4\n3\nr: 1\nseed: 2\n").unwrap();
+		assert_eq!(header.get("seed"), None);
+		assert!(comms.iter().any(|c| *c.verb() == '#'));
+	}
+}
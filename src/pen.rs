@@ -0,0 +1,150 @@
+use super::brush::*;
+use super::mask::*;
+use super::pyxel::*;
+use super::symmetry::*;
+
+// Everything that decides where a put actually lands on the canvas
+#[derive(Debug, Clone)]
+pub struct Pen {
+	symmetry: Symmetry,
+	brush: Brush,
+	color: [u8; 4],
+	down: bool,
+	antialias: bool,
+	erasing: bool,
+	mask: Option<Mask>,
+}
+
+impl Pen {
+	// Returns a new Pen. Takes no arguments.
+	pub fn new() -> Self {
+		Pen {
+			symmetry: Symmetry::None,
+			brush: Brush::Square(1),
+			color: [0, 0, 0, 255],
+			down: false,
+			antialias: false,
+			erasing: false,
+			mask: None,
+		}
+	}
+
+	// Returns down. Whether moving the pointer paints.
+	pub fn down(&self) -> bool {
+		self.down
+	}
+
+	// Returns antialias. Whether lines, paths and ellipses blend their edges.
+	pub fn antialias(&self) -> bool {
+		self.antialias
+	}
+
+	// Returns how many pyxels a single put may paint, with the brush and every mirror
+	pub fn reach(&self) -> usize {
+		self.brush.offsets().len().max(1) * self.symmetry.points(0, 0).len()
+	}
+
+	// Set respective values
+	pub fn set_symmetry(&mut self, symmetry: Symmetry) {
+		self.symmetry = symmetry;
+	}
+
+	pub fn set_brush(&mut self, brush: Brush) {
+		self.brush = brush;
+	}
+
+	// Also stops erasing
+	pub fn set_color(&mut self, color: [u8; 4]) {
+		self.color = color;
+		self.erasing = false;
+	}
+
+	// Makes strokes clear pyxels to transparency instead of painting them
+	pub fn set_erasing(&mut self, erasing: bool) {
+		self.erasing = erasing;
+	}
+
+	pub fn set_down(&mut self, down: bool) {
+		self.down = down;
+	}
+
+	pub fn set_antialias(&mut self, antialias: bool) {
+		self.antialias = antialias;
+	}
+
+	// Only lets puts land where the mask allows, or anywhere on None
+	pub fn set_mask(&mut self, mask: Option<Mask>) {
+		self.mask = mask;
+	}
+
+	// Puts a pyxel at (x, y) and at every mirrored position, skipping those off the canvas
+	pub fn put(&self, pyxels: &mut PyxelVec, color: [u8; 4], x: isize, y: isize) {
+		for (px, py) in self.symmetry.points(x, y) {
+			self.push(pyxels, Pyxel::new(color, 0, 0), px, py);
+		}
+	}
+
+	// Paints the pen's color at each point in turn, as color commands do.
+	// With the default 1 by 1 brush every point is put as is, in order and however often it repeats,
+	// so see-through colors build up where puts wrap onto each other. Other brushes paint a stroke.
+	pub fn puts(&self, pyxels: &mut PyxelVec, points: &[(isize, isize)]) {
+		if self.brush.offsets() != [(0, 0)] {
+			self.stroke(pyxels, points);
+			return;
+		}
+		let a = self.color[3];
+		if a == 0 {
+			return;
+		}
+		for &(x, y) in points.iter() {
+			for (px, py) in self.symmetry.points(x, y) {
+				if self.erasing {
+					self.push(pyxels, Pyxel::eraser(a, 0, 0), px, py);
+				} else {
+					self.push(pyxels, Pyxel::new(self.color, 0, 0), px, py);
+				}
+			}
+		}
+	}
+
+	// Paints the brush at every point. Each pyxel is painted once however many times the stroke covers it.
+	pub fn stroke(&self, pyxels: &mut PyxelVec, points: &[(isize, isize)]) {
+		let points: Vec<(isize, isize, f32)> = points.iter().map(|&(x, y)| (x, y, 1.0)).collect();
+		self.stroke_coverage(pyxels, &points);
+	}
+
+	// Like stroke, but each point only partly covers its pyxel, from 0 to 1.
+	// The color's alpha is scaled by the most any point covers the pyxel.
+	pub fn stroke_coverage(&self, pyxels: &mut PyxelVec, points: &[(isize, isize, f32)]) {
+		let color = self.color;
+		let offsets = self.brush.offsets();
+		let mut covered = vec![];
+		for (x, y, coverage) in points.iter() {
+			for (ox, oy) in offsets.iter() {
+				for (px, py) in self.symmetry.points(x + ox, y + oy) {
+					covered.push((px, py, *coverage));
+				}
+			}
+		}
+		covered.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)).then(b.2.partial_cmp(&a.2).unwrap()));
+		covered.dedup_by_key(|p| (p.0, p.1));
+		for (px, py, coverage) in covered {
+			let a = (color[3] as f32 * coverage.min(1.0)).round() as u8;
+			if a == 0 {
+				continue;
+			}
+			if self.erasing {
+				self.push(pyxels, Pyxel::eraser(a, 0, 0), px, py);
+			} else {
+				self.push(pyxels, Pyxel::new([color[0], color[1], color[2], a], 0, 0), px, py);
+			}
+		}
+	}
+
+	// Pushes a copy of the pyxel moved to (x, y) if that is on the canvas and inside the mask
+	fn push(&self, pyxels: &mut PyxelVec, pyxel: Pyxel, x: isize, y: isize) {
+		if pyxels.contains(x, y) && self.mask.as_ref().map_or(true, |m| m.allows(x, y)) {
+			pyxels.push(pyxel.at(x, y));
+		}
+	}
+}
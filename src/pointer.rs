@@ -1,7 +1,36 @@
+use super::traversal::*;
+
+// What the pointer does when it reaches the edge of its bounds along an axis
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Edge {
+	Wrap,
+	Torus,
+	Clamp,
+	Bounce,
+	Stop,
+	Grow,
+}
+
+impl Edge {
+	// Returns the Edge called name, if there is one
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"wrap" => Some(Edge::Wrap),
+			"torus" => Some(Edge::Torus),
+			"clamp" => Some(Edge::Clamp),
+			"bounce" => Some(Edge::Bounce),
+			"stop" => Some(Edge::Stop),
+			"grow" => Some(Edge::Grow),
+			_ => None,
+		}
+	}
+}
+
 // Not a memory pointer (I mean not technically but you get it)
 // Points to a position on a Canvas
 #[derive(Debug, Clone)]
 pub struct Pointer {
+	name: String,
 	x: isize,
 	y: isize,
 	reverse_move_x: bool,
@@ -12,12 +41,21 @@ pub struct Pointer {
 	bottom: isize,
 	left: isize,
 	right: isize,
+	heading: f64,
+	frac_x: f64,
+	frac_y: f64,
+	advance_x: isize,
+	advance_y: isize,
+	edge_x: Edge,
+	edge_y: Edge,
+	traversal: Traversal,
 }
 
 impl Pointer {
 	// Returns a new pointer. Takes no arguments.
 	pub fn new() -> Self {
 		Pointer {
+			name: String::from("main"),
 			x: 0,
 			y: 0,
 			reverse_move_x: false,
@@ -28,6 +66,14 @@ impl Pointer {
 			bottom: 32,
 			left: 0,
 			right: 32,
+			heading: 0.0,
+			frac_x: 0.0,
+			frac_y: 0.0,
+			advance_x: 1,
+			advance_y: 0,
+			edge_x: Edge::Wrap,
+			edge_y: Edge::Torus,
+			traversal: Traversal::Row,
 		}
 	}
 
@@ -72,94 +118,130 @@ impl Pointer {
 		self.right
 	}
 
+	// Returns name. What commands call the pointer by, main for the first.
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	pub fn set_name(&mut self, name: &str) {
+		self.name = name.to_string();
+	}
+
+	// Returns heading. The direction the turtle faces in degrees, counterclockwise from facing right.
+	pub fn heading(&self) -> f64 {
+		self.heading
+	}
+
+	pub fn set_heading(&mut self, degrees: f64) {
+		self.heading = degrees.rem_euclid(360.0);
+	}
+
+	// Turns the turtle counterclockwise, or clockwise if degrees is negative
+	pub fn turn(&mut self, degrees: f64) {
+		self.set_heading(self.heading() + degrees);
+	}
+
+	// Returns how many pyxels away the turtle would go moving n forward, keeping the part of a pyxel left over for next time
+	pub fn forward(&mut self, n: f64) -> (isize, isize) {
+		let radians = self.heading().to_radians();
+		let x = self.frac_x + n * radians.cos();
+		let y = self.frac_y - n * radians.sin();
+		let (dx, dy) = (x.round(), y.round());
+		self.frac_x = x - dx;
+		self.frac_y = y - dy;
+		(dx as isize, dy as isize)
+	}
+
+	// Sets which way the pointer moves after putting a pyxel (default: 1, 0, to the right)
+	pub fn set_advance(&mut self, r: isize, d: isize) {
+		self.advance_x = r;
+		self.advance_y = d;
+	}
+
+	// Returns how many pyxels advancing after a put steps through
+	pub fn advance_steps(&self) -> usize {
+		if self.traversal == Traversal::Row {
+			self.advance_x.unsigned_abs().saturating_add(self.advance_y.unsigned_abs()).max(1)
+		} else {
+			1
+		}
+	}
+
+	// Sets the order puts follow, and moves the pointer to the first pyxel in it
+	pub fn set_traversal(&mut self, traversal: Traversal) {
+		self.traversal = traversal;
+		self.set_pos(self.left(), self.top());
+	}
+
+	// Moves the pointer on after putting a pyxel
+	pub fn advance(&mut self) {
+		if self.traversal == Traversal::Row {
+			self.slide(self.advance_x, self.advance_y);
+		} else {
+			let (x, y) = self.traversal.next(self.x(), self.y(), self.left(), self.top(), self.right(), self.bottom());
+			self.set_pos(x, y);
+		}
+	}
+
+	// Sets what happens at the left and right edges, and at the top and bottom
+	pub fn set_edges(&mut self, x: Edge, y: Edge) {
+		self.edge_x = x;
+		self.edge_y = y;
+	}
+
+	// Returns whether the pointer is past an edge that stops it, where puts are thrown away
+	pub fn stopped(&self) -> bool {
+		let out_x = self.x() < self.left() || self.x() >= self.right();
+		let out_y = self.y() < self.top() || self.y() >= self.bottom();
+		(self.edge_x == Edge::Stop && out_x) || (self.edge_y == Edge::Stop && out_y)
+	}
+
+	// Returns whether (x, y) is inside the virtual bounds. Edges that grow have no bounds along their axis.
+	pub fn in_bounds(&self, x: isize, y: isize) -> bool {
+		(self.edge_x == Edge::Grow || (x >= self.left() && x < self.right()))
+			&& (self.edge_y == Edge::Grow || (y >= self.top() && y < self.bottom()))
+	}
+
 	pub fn set_pos(&mut self, r: isize, d: isize) {
 		self.x = r;
 		self.y = d;
 	}
 
+	// Brings the pointer back inside its bounds, the way the edge it is past says to
 	pub fn check_pos(&mut self) {
-		let mut x = self.x();
-		let mut y = self.y();
-		
-		if x < self.left() {
-			if self.reverse_move_x() {
-				x = self.right() - 1;
-			} else {
-				x = self.left();
-			}
-		}
-		if x >= self.right() {
-			if self.reverse_move_x() {
-				x = self.right();
-			} else {
-				x = self.left();
-			}
-		}
-		
-		if y < self.top() {
-			y = self.bottom() - 1;
-		}
-		if y >= self.bottom() {
-			y = self.top();
-		}
+		let x = settle(self.edge_x, self.x(), self.left(), self.right(), self.reverse_move_x());
+		let y = settle(self.edge_y, self.y(), self.top(), self.bottom(), self.reverse_move_y());
 		self.set_pos(x, y);
 	}
 
+	// Moves the pointer one pyxel at a time, doing what the edge says whenever it would cross one
 	pub fn move_pos(&mut self, r: isize, d: isize) {
-		let mut r_unit = 0;
-		if r != 0 {
-			r_unit = r/r.abs();
-		}
-		let r_unit = r_unit;
-		let mut d_unit = 0;
-		if d != 0 {
-			d_unit = d/d.abs();
-		}
-		let d_unit = d_unit;
+		let mut r_unit = r.signum();
+		let mut d_unit = d.signum();
 		let mut x = self.x();
 		let mut y = self.y();
 		
-		for i in 0..r.abs() {
-			let t_r = x + r_unit;
-			if t_r >= self.left() && t_r < self.right() {
-				x += r_unit;
+		for _ in 0..r.abs() {
+			let (next, carry) = step(self.edge_x, x + r_unit, self.left(), self.right(), self.reverse_move_x());
+			if self.edge_x == Edge::Bounce && next != x + r_unit {
+				r_unit = -r_unit;
+				self.flip_reverse_move_x();
 			}
-			if t_r < self.left() {
-				if self.reverse_move_x() {
-					x = self.right() - 1;
-					if self.reverse_move_y() {
-						y -= 1;
-					} else {
-						y += 1;
-					}
-				} else {
-					x = self.left();
-				}
-			}
-			if t_r >= self.right() {
-				if self.reverse_move_x() {
-					x = self.right() - 1;
-				} else {
-					x = self.left();
-					if self.reverse_move_y() {
-						y -= 1;
-					} else {
-						y += 1;
-					}
-				}
+			x = next;
+			if carry {
+				y += if self.reverse_move_y() { -1 } else { 1 };
 			}
 		}
 		
-		for i in 0..d.abs() {
-			let t_d = y + d_unit;
-			if t_d >= self.top() && t_d < self.bottom() {
-				y += d_unit;
-			}
-			if t_d < self.top() {
-				y = self.bottom() - 1;
+		for _ in 0..d.abs() {
+			let (next, carry) = step(self.edge_y, y + d_unit, self.top(), self.bottom(), self.reverse_move_y());
+			if self.edge_y == Edge::Bounce && next != y + d_unit {
+				d_unit = -d_unit;
+				self.flop_reverse_move_y();
 			}
-			if t_d >= self.bottom() {
-				y = self.top();
+			y = next;
+			if carry {
+				x += if self.reverse_move_x() { -1 } else { 1 };
 			}
 		}
 		self.set_pos(x, y);
@@ -221,18 +303,145 @@ impl Pointer {
 		self.height = h;
 		self.set_virtual_bottom(0);
 	}
+}
 
-	// Blank the pointer! >:D
-	pub fn blank(&mut self, w: usize, h: usize) {
-		self.x = 0;
-		self.y = 0;
-		self.reverse_move_x = false;
-		self.reverse_move_y = false;
-		self.top = 0;
-		self.width = w;
-		self.height = h;
-		self.bottom = self.height() as isize;
-		self.left = 0;
-		self.right = self.width() as isize;
+// Returns where a step to next along an axis from lo to hi (not included) lands,
+// and whether it went on to the next line (or column)
+fn step(edge: Edge, next: isize, lo: isize, hi: isize, reverse: bool) -> (isize, bool) {
+	if next >= lo && next < hi {
+		return (next, false);
+	}
+	let low = next < lo;
+	match edge {
+		// Only moving the way the pointer writes goes on to the next line. Moving back stops at the start.
+		Edge::Wrap if low == reverse => (if low { hi - 1 } else { lo }, true),
+		Edge::Torus => (if low { hi - 1 } else { lo }, false),
+		Edge::Bounce if low => ((2 * lo - next).min(hi - 1), false),
+		Edge::Bounce => ((2 * (hi - 1) - next).max(lo), false),
+		// Goes one past the edge and no further
+		Edge::Stop => (next.max(lo - 1).min(hi), false),
+		Edge::Grow => (next, false),
+		_ => (if low { lo } else { hi - 1 }, false),
+	}
+}
+
+// Returns where n ends up along an axis from lo to hi (not included) when brought back inside
+fn settle(edge: Edge, n: isize, lo: isize, hi: isize, reverse: bool) -> isize {
+	if n >= lo && n < hi {
+		return n;
+	}
+	let low = n < lo;
+	match edge {
+		// The start of the line
+		Edge::Wrap => if reverse { hi - 1 } else { lo },
+		Edge::Torus => if low { hi - 1 } else { lo },
+		Edge::Stop | Edge::Grow => n,
+		_ => if low { lo } else { hi - 1 },
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn step_inside() {
+		for &edge in &[Edge::Wrap, Edge::Torus, Edge::Clamp, Edge::Bounce, Edge::Stop, Edge::Grow] {
+			assert_eq!(step(edge, 3, 0, 8, false), (3, false));
+			assert_eq!(settle(edge, 3, 0, 8, false), 3);
+		}
+	}
+
+	#[test]
+	fn step_wrap() {
+		assert_eq!(step(Edge::Wrap, 8, 0, 8, false), (0, true));
+		assert_eq!(step(Edge::Wrap, -1, 0, 8, true), (7, true));
+		// Moving back against the way it writes stops at the start of the line
+		assert_eq!(step(Edge::Wrap, -1, 0, 8, false), (0, false));
+		assert_eq!(settle(Edge::Wrap, 9, 0, 8, false), 0);
+		assert_eq!(settle(Edge::Wrap, -2, 0, 8, true), 7);
+	}
+
+	#[test]
+	fn step_torus() {
+		assert_eq!(step(Edge::Torus, 8, 0, 8, false), (0, false));
+		assert_eq!(step(Edge::Torus, -1, 0, 8, false), (7, false));
+		assert_eq!(settle(Edge::Torus, 9, 0, 8, false), 0);
+		assert_eq!(settle(Edge::Torus, -2, 0, 8, false), 7);
+	}
+
+	#[test]
+	fn step_clamp() {
+		assert_eq!(step(Edge::Clamp, 8, 0, 8, false), (7, false));
+		assert_eq!(step(Edge::Clamp, -1, 0, 8, false), (0, false));
+		assert_eq!(settle(Edge::Clamp, 20, 0, 8, false), 7);
+		assert_eq!(settle(Edge::Clamp, -20, 0, 8, false), 0);
+	}
+
+	#[test]
+	fn step_bounce() {
+		assert_eq!(step(Edge::Bounce, 8, 0, 8, false), (6, false));
+		assert_eq!(step(Edge::Bounce, -1, 0, 8, false), (1, false));
+		// Too narrow to bounce back into
+		assert_eq!(step(Edge::Bounce, 1, 0, 1, false), (0, false));
+		assert_eq!(settle(Edge::Bounce, 20, 0, 8, false), 7);
+		assert_eq!(settle(Edge::Bounce, -20, 0, 8, false), 0);
+	}
+
+	#[test]
+	fn step_stop() {
+		assert_eq!(step(Edge::Stop, 8, 0, 8, false), (8, false));
+		assert_eq!(step(Edge::Stop, 9, 0, 8, false), (8, false));
+		assert_eq!(step(Edge::Stop, -3, 0, 8, false), (-1, false));
+		assert_eq!(settle(Edge::Stop, 8, 0, 8, false), 8);
+	}
+
+	#[test]
+	fn step_grow() {
+		assert_eq!(step(Edge::Grow, 100, 0, 8, false), (100, false));
+		assert_eq!(step(Edge::Grow, -100, 0, 8, false), (-100, false));
+		assert_eq!(settle(Edge::Grow, -100, 0, 8, false), -100);
+	}
+
+	#[test]
+	fn grow_has_no_bounds() {
+		let mut pointer = Pointer::new();
+		assert!(!pointer.in_bounds(-5, 3));
+		pointer.set_edges(Edge::Grow, Edge::Wrap);
+		assert!(pointer.in_bounds(-5, 3));
+		assert!(pointer.in_bounds(1000, 3));
+		assert!(!pointer.in_bounds(-5, 32));
+	}
+
+	#[test]
+	fn move_pos_bounces_back() {
+		let mut pointer = Pointer::new();
+		pointer.set_edges(Edge::Bounce, Edge::Clamp);
+		pointer.set_width(4);
+		pointer.move_pos(5, 0);
+		assert_eq!(pointer.x(), 1);
+		assert!(pointer.reverse_move_x());
+		pointer.move_pos(0, 100);
+		assert_eq!(pointer.y(), 31);
+	}
+
+	#[test]
+	fn move_pos_wraps_to_next_line() {
+		let mut pointer = Pointer::new();
+		pointer.set_width(4);
+		pointer.move_pos(6, 0);
+		assert_eq!((pointer.x(), pointer.y()), (2, 1));
+	}
+
+	#[test]
+	fn turtle_turns_and_keeps_what_is_left_over() {
+		let mut pointer = Pointer::new();
+		assert_eq!(pointer.forward(3.0), (3, 0));
+		pointer.turn(90.0);
+		assert_eq!(pointer.forward(2.0), (0, -2));
+		pointer.turn(-450.0);
+		assert_eq!(pointer.heading(), 0.0);
+		let steps: Vec<(isize, isize)> = (0..5).map(|_| pointer.forward(0.4)).collect();
+		assert_eq!(steps, [(0, 0), (1, 0), (0, 0), (1, 0), (0, 0)]);
 	}
 }
@@ -1,3 +1,7 @@
+use super::background::*;
+use super::cmd::Span;
+use super::layer::*;
+
 // The Pyxel. Purposely spelled with a Y to differentiate it from normal pixels.
 #[derive(Debug, Copy, Clone)]
 pub struct Pyxel {
@@ -5,26 +9,39 @@ pub struct Pyxel {
 	g: u8,
 	b: u8,
 	a: u8,
-	x: usize,
-	y: usize,
+	x: isize,
+	y: isize,
+	erase: bool,
+	span: Span,
 }
 
 impl Pyxel {
-	// Returns a new Pyxel. Takes an array of 4 u8s and two usizes as args
-	pub fn new(color: [u8; 4], x: usize, y: usize) -> Pyxel {
+	// Returns a new Pyxel. Takes an array of 4 u8s and two isizes as args
+	pub fn new(color: [u8; 4], x: isize, y: isize) -> Pyxel {
 		Pyxel {
 			r: color[0],
 			g: color[1],
 			b: color[2],
 			a: color[3],
-			x: x,
-			y: y,
+			x,
+			y,
+			erase: false,
+			span: Span::default(),
 		}
 	}
 
-	// Returns the channels formatted as an array of u8
-	pub fn color_u8(&self) -> [u8; 4] {
-		[self.r(), self.g(), self.b(), self.a()]
+	// Returns a new Pyxel that clears what is below it instead of covering it. Takes how much to clear as alpha.
+	pub fn eraser(a: u8, x: isize, y: isize) -> Pyxel {
+		Pyxel {
+			r: 0,
+			g: 0,
+			b: 0,
+			a,
+			x,
+			y,
+			erase: true,
+			span: Span::default(),
+		}
 	}
 
 	// Returns the channels formatted as an array of f32
@@ -54,59 +71,314 @@ impl Pyxel {
 		self.a
 	}
 
-	pub fn x(&self) -> usize {
+	pub fn x(&self) -> isize {
 		self.x
 	}
 
-	pub fn y(&self) -> usize {
+	pub fn y(&self) -> isize {
 		self.y
 	}
+
+	pub fn erase(&self) -> bool {
+		self.erase
+	}
+
+	// Returns span. Where the command that drew it was written.
+	pub fn span(&self) -> Span {
+		self.span
+	}
+
+	// Returns the same Pyxel somewhere else
+	pub fn at(&self, x: isize, y: isize) -> Pyxel {
+		Pyxel { x, y, ..*self }
+	}
 }
 
+// The canvas. Pyxels are pushed onto the current Layer.
+// Along an infinite axis the canvas grows to fit whatever is drawn, even at negative positions.
 pub struct PyxelVec {
-	pyxels: Vec<Pyxel>,
+	layers: Vec<Layer>,
+	current: usize,
 	width: usize,
 	height: usize,
+	infinite_x: bool,
+	infinite_y: bool,
+	padding: usize,
+	span: Span,
 }
 
 impl PyxelVec {
 	pub fn new(width: usize, height: usize) -> PyxelVec {
 		PyxelVec {
-			pyxels: vec!(),
-			width: width,
-			height: height,
+			layers: vec![Layer::new("base")],
+			current: 0,
+			width,
+			height,
+			infinite_x: false,
+			infinite_y: false,
+			padding: 0,
+			span: Span::default(),
 		}
 	}
 	
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	// Makes the canvas grow along the x and y axes
+	pub fn set_infinite(&mut self, x: bool, y: bool) {
+		self.infinite_x = x;
+		self.infinite_y = y;
+	}
+
+	// Space left around what is drawn along infinite axes
+	pub fn set_padding(&mut self, padding: usize) {
+		self.padding = padding;
+	}
+
+	// Marks every pyxel pushed from now on as drawn by the command written at span
+	pub fn set_span(&mut self, span: Span) {
+		self.span = span;
+	}
+
+	// Returns whether a pyxel at (x, y) would be on the canvas
+	pub fn contains(&self, x: isize, y: isize) -> bool {
+		(self.infinite_x || (x >= 0 && (x as usize) < self.width))
+			&& (self.infinite_y || (y >= 0 && (y as usize) < self.height))
+	}
+
+	// Returns the part of the canvas that is output as x, y, width and height.
+	// Along infinite axes it is cropped to what has been painted, plus the padding.
+	pub fn area(&self) -> (isize, isize, usize, usize) {
+		if !self.infinite_x && !self.infinite_y {
+			return (0, 0, self.width, self.height);
+		}
+		let painted = self.layers.iter().flat_map(|l| l.pyxels().iter()).filter(|p| !p.erase());
+		let (mut x0, mut y0, mut x1, mut y1) = (isize::MAX, isize::MAX, isize::MIN, isize::MIN);
+		for p in painted {
+			x0 = x0.min(p.x());
+			y0 = y0.min(p.y());
+			x1 = x1.max(p.x().saturating_add(1));
+			y1 = y1.max(p.y().saturating_add(1));
+		}
+		// Far enough out the size is only too big, which the pixel limit catches
+		let pad = self.padding.min(isize::MAX as usize) as isize;
+		let (x, w) = if !self.infinite_x {
+			(0, self.width)
+		} else if x0 < x1 {
+			(x0.saturating_sub(pad), (x1.saturating_sub(x0) as usize).saturating_add(self.padding.saturating_mul(2)))
+		} else {
+			(0, self.padding.saturating_mul(2).saturating_add(1))
+		};
+		let (y, h) = if !self.infinite_y {
+			(0, self.height)
+		} else if y0 < y1 {
+			(y0.saturating_sub(pad), (y1.saturating_sub(y0) as usize).saturating_add(self.padding.saturating_mul(2)))
+		} else {
+			(0, self.padding.saturating_mul(2).saturating_add(1))
+		};
+		(x, y, w, h)
+	}
+
+	pub fn layers(&self) -> &[Layer] {
+		&self.layers
+	}
+
+	pub fn layer_mut(&mut self) -> &mut Layer {
+		&mut self.layers[self.current]
+	}
+
+	// Makes the named layer the current one, putting a new layer on top if there is none by that name
+	pub fn select_layer(&mut self, name: &str) {
+		match self.layers.iter().position(|l| l.name() == name) {
+			Some(i) => self.current = i,
+			None => {
+				self.layers.push(Layer::new(name));
+				self.current = self.layers.len() - 1;
+			}
+		}
+	}
+
 	pub fn push(&mut self, pyxel: Pyxel) {
-		self.pyxels.push(pyxel);
+		let span = self.span;
+		self.layers[self.current].pyxels_mut().push(Pyxel { span, ..pyxel });
 	}
 	
-	pub fn to_vec_u8(&self, dark: bool) -> Vec<u8> {
-		let mut v = vec!();
-		if dark {
-			v = vec![0u8; self.width * self.height * 3];
-		} else {
-			v = vec![255u8; self.width * self.height * 3];
+	// Flattens every layer onto the background. Returns RGBA if alpha is true, otherwise RGB.
+	pub fn to_vec_u8(&self, background: &Background, alpha: bool) -> Vec<u8> {
+		channels(self.composite(&self.layers, background), alpha)
+	}
+
+	// Flattens a single layer onto the background
+	pub fn layer_to_vec_u8(&self, i: usize, background: &Background, alpha: bool) -> Vec<u8> {
+		channels(self.composite(&self.layers[i..=i], background), alpha)
+	}
+
+	// Returns the layers flattened onto the background as RGBA
+	fn composite(&self, layers: &[Layer], background: &Background) -> Vec<u8> {
+		let (x, y, width, height) = self.area();
+		let background = background.fill(width, height);
+		let mut v = background.clone();
+		for (n, layer) in layers.iter().enumerate() {
+			// Erasers on the bottom layer uncover the background, so it can be drawn straight onto it
+			let erases = n > 0 && layer.pyxels().iter().any(|p| p.erase());
+			if layer.blend() == Blend::Normal && layer.opacity() >= 1.0 && !erases {
+				composite_normal(&mut v, (x, y, width, height), layer.pyxels(), &background);
+			} else {
+				let flat = layer.flatten_region(x, y, width, height);
+				for (i, src) in flat.iter().enumerate() {
+					let a = src[3] * layer.opacity();
+					if a <= 0.0 {
+						continue;
+					}
+					let ab = v[i * 4 + 3] as f32 / 255.0;
+					let ao = a + ab * (1.0 - a);
+					for c in 0..3 {
+						let cb = v[i * 4 + c] as f32 / 255.0;
+						let cs = (1.0 - ab) * src[c] + ab * layer.blend().channel(cb, src[c]);
+						v[i * 4 + c] = ((a * cs + (1.0 - a) * ab * cb) / ao * 255.0).round() as u8;
+					}
+					v[i * 4 + 3] = (ao * 255.0).round() as u8;
+				}
+			}
 		}
-		for p in self.pyxels.iter() {
+		v
+	}
+
+	// Composites the pyxels inside a region of the current layer over transparency
+	pub fn flatten_region(&self, x: isize, y: isize, w: usize, h: usize) -> Vec<[f32; 4]> {
+		self.layers[self.current].flatten_region(x, y, w, h)
+	}
+
+	// Rearranges what has been drawn inside a region of the current layer.
+	// `to` maps a position relative to the region to its new position on the canvas, or None to drop it.
+	pub fn transform<F>(&mut self, x: isize, y: isize, w: usize, h: usize, to: F)
+	where
+		F: Fn(usize, usize) -> Option<(isize, isize)>,
+	{
+		let (wi, hi) = (w as isize, h as isize);
+		let flat = self.flatten_region(x, y, w, h);
+		let mut moved = vec![];
+		for (i, color) in flat.iter().enumerate() {
+			if color[3] <= 0.0 {
+				continue;
+			}
+			if let Some((nx, ny)) = to(i % w, i / w) {
+				if self.contains(nx, ny) {
+					let c = [
+						(color[0] * 255.0).round() as u8,
+						(color[1] * 255.0).round() as u8,
+						(color[2] * 255.0).round() as u8,
+						(color[3] * 255.0).round() as u8,
+					];
+					moved.push(Pyxel {
+						span: self.span,
+						..Pyxel::new(c, nx, ny)
+					});
+				}
+			}
+		}
+		let pyxels = self.layer_mut().pyxels_mut();
+		pyxels.retain(|p| p.x() < x || p.y() < y || p.x() >= x + wi || p.y() >= y + hi);
+		pyxels.extend(moved);
+	}
+}
+
+// Alpha blends pyxels one by one straight onto an RGBA buffer of the area x, y, width, height
+fn composite_normal(v: &mut [u8], area: (isize, isize, usize, usize), pyxels: &[Pyxel], background: &[u8]) {
+	let (x, y, width, height) = area;
+	for p in pyxels.iter() {
+		let (px, py) = (p.x() - x, p.y() - y);
+		if px < 0 || py < 0 || px as usize >= width || py as usize >= height {
+			continue;
+		}
+		let i = (py as usize * width + px as usize) * 4;
+		let a = p.a() as f32 / 255.0;
+
+		if p.erase() {
+			// Fade towards the background
+			let av = v[i + 3] as f32 / 255.0;
+			let ag = background[i + 3] as f32 / 255.0;
+			let ao = (1.0 - a) * av + a * ag;
+			if ao > 0.0 {
+				for c in 0..3 {
+					let mixed = (1.0 - a) * v[i + c] as f32 * av + a * background[i + c] as f32 * ag;
+					v[i + c] = (mixed / ao).round() as u8;
+				}
+			}
+			v[i + 3] = (ao * 255.0).round() as u8;
+		} else if v[i + 3] == 255 {
 			let r = p.r() as f32 / 255.0;
 			let g = p.g() as f32 / 255.0;
 			let b = p.b() as f32 / 255.0;
-			let a = p.a() as f32 / 255.0;
 			
-			let or = v[((p.y() * self.width * 3) + p.x() * 3) + 0] as f32 / 255.0;
-			let og = v[((p.y() * self.width * 3) + p.x() * 3) + 1] as f32 / 255.0;
-			let ob = v[((p.y() * self.width * 3) + p.x() * 3) + 2] as f32 / 255.0;
+			let or = v[i] as f32 / 255.0;
+			let og = v[i + 1] as f32 / 255.0;
+			let ob = v[i + 2] as f32 / 255.0;
 			
 			let nr = ((a * r + (1.0 - a) * or) * 255.0) as u8;
 			let ng = ((a * g + (1.0 - a) * og) * 255.0) as u8;
 			let nb = ((a * b + (1.0 - a) * ob) * 255.0) as u8;
 			
-			v[((p.y() * self.width * 3) + p.x() * 3) + 0] = nr;
-			v[((p.y() * self.width * 3) + p.x() * 3) + 1] = ng;
-			v[((p.y() * self.width * 3) + p.x() * 3) + 2] = nb;
+			v[i] = nr;
+			v[i + 1] = ng;
+			v[i + 2] = nb;
+		} else {
+			// Over something see-through
+			let src = p.color_f32();
+			let ab = v[i + 3] as f32 / 255.0;
+			let ao = a + ab * (1.0 - a);
+			if ao <= 0.0 {
+				continue;
+			}
+			for c in 0..3 {
+				let cb = v[i + c] as f32 / 255.0;
+				v[i + c] = ((src[c] * a + cb * ab * (1.0 - a)) / ao * 255.0).round() as u8;
+			}
+			v[i + 3] = (ao * 255.0).round() as u8;
 		}
+	}
+}
+
+// Drops the alpha of an RGBA buffer unless it is wanted
+fn channels(v: Vec<u8>, alpha: bool) -> Vec<u8> {
+	if alpha {
 		v
+	} else {
+		v.chunks(4).flat_map(|p| p[..3].to_vec()).collect()
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const RED: [u8; 4] = [255, 0, 0, 255];
+
+	#[test]
+	fn erasing_shows_the_background() {
+		let mut pyxels = PyxelVec::new(2, 1);
+		pyxels.push(Pyxel::new(RED, 0, 0));
+		pyxels.push(Pyxel::new(RED, 1, 0));
+		pyxels.push(Pyxel::eraser(255, 1, 0));
+		let blue = Background::Color([0, 0, 255, 255]);
+		assert_eq!(pyxels.to_vec_u8(&blue, false), [255, 0, 0, 0, 0, 255]);
+		let clear = Background::from_name("transparent").unwrap();
+		let rgba = pyxels.to_vec_u8(&clear, true);
+		assert_eq!((rgba[3], rgba[7]), (255, 0));
+	}
+
+	#[test]
+	fn erasing_a_layer_shows_the_layers_below() {
+		let mut pyxels = PyxelVec::new(1, 1);
+		pyxels.push(Pyxel::new(RED, 0, 0));
+		pyxels.select_layer("top");
+		pyxels.push(Pyxel::new([0, 255, 0, 255], 0, 0));
+		pyxels.push(Pyxel::eraser(255, 0, 0));
+		assert_eq!(pyxels.to_vec_u8(&Background::Color([0, 0, 0, 255]), true), RED);
+	}
+}
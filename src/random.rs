@@ -0,0 +1,78 @@
+// A small seeded random number generator (SplitMix64).
+// Kept in house so the same seed draws the same image on every version and platform.
+#[derive(Debug, Clone)]
+pub struct Random {
+	state: u64,
+}
+
+impl Random {
+	// Returns a new Random. Takes the seed.
+	pub fn new(seed: u64) -> Self {
+		Random {
+			state: seed,
+		}
+	}
+
+	// Returns the next number in the sequence
+	pub fn next_u64(&mut self) -> u64 {
+		self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = self.state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		z ^ (z >> 31)
+	}
+
+	// Returns a number from 0 up to but not including 1
+	pub fn next_f64(&mut self) -> f64 {
+		(self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+	}
+
+	// Returns a whole number from a to b, both included
+	pub fn range(&mut self, a: i64, b: i64) -> i64 {
+		let (lo, hi) = if a <= b { (a, b) } else { (b, a) };
+		// The whole range of i64 is 2^64 numbers, one more than a u64 holds
+		let span = (hi as i128 - lo as i128) as u128 + 1;
+		let n = if span > u64::MAX as u128 {
+			self.next_u64()
+		} else {
+			(self.next_u64() as u128 % span) as u64
+		};
+		(lo as i128 + n as i128) as i64
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn range_stays_inside() {
+		let mut random = Random::new(7);
+		for _ in 0..1000 {
+			let n = random.range(-3, 5);
+			assert!((-3..=5).contains(&n));
+		}
+		assert_eq!(random.range(4, 4), 4);
+		assert!((1..=9).contains(&random.range(9, 1)));
+	}
+
+	#[test]
+	fn range_of_every_i64() {
+		let mut random = Random::new(0);
+		for _ in 0..100 {
+			random.range(i64::MIN, i64::MAX);
+		}
+		let n = random.range(i64::MAX - 1, i64::MAX);
+		assert!(n >= i64::MAX - 1);
+		let n = random.range(i64::MIN, i64::MIN + 1);
+		assert!(n <= i64::MIN + 1);
+	}
+
+	#[test]
+	fn same_seed_same_numbers() {
+		let (mut a, mut b) = (Random::new(42), Random::new(42));
+		for _ in 0..10 {
+			assert_eq!(a.range(0, 100), b.range(0, 100));
+		}
+	}
+}
@@ -0,0 +1,104 @@
+// Returns every position on the line from (x0, y0) to (x1, y1), both ends included
+pub fn line(x0: isize, y0: isize, x1: isize, y1: isize) -> Vec<(isize, isize)> {
+	// Bresenham's
+	let dx = (x1 - x0).abs();
+	let dy = -(y1 - y0).abs();
+	let sx = if x0 < x1 { 1 } else { -1 };
+	let sy = if y0 < y1 { 1 } else { -1 };
+	let mut err = dx + dy;
+	let (mut x, mut y) = (x0, y0);
+	let mut v = vec![];
+	loop {
+		v.push((x, y));
+		if x == x1 && y == y1 {
+			break;
+		}
+		let e2 = 2 * err;
+		if e2 >= dy {
+			err += dy;
+			x += sx;
+		}
+		if e2 <= dx {
+			err += dx;
+			y += sy;
+		}
+	}
+	v
+}
+
+// Returns the positions on the line from (x0, y0) to (x1, y1) with how much of each the line covers, from 0 to 1
+pub fn wu_line(x0: isize, y0: isize, x1: isize, y1: isize) -> Vec<(isize, isize, f32)> {
+	// Xiaolin Wu's. The ends are always whole pyxels, so only the middle needs blending.
+	let steep = (y1 - y0).abs() > (x1 - x0).abs();
+	let (mut x0, mut y0, mut x1, mut y1) = if steep { (y0, x0, y1, x1) } else { (x0, y0, x1, y1) };
+	if x0 > x1 {
+		std::mem::swap(&mut x0, &mut x1);
+		std::mem::swap(&mut y0, &mut y1);
+	}
+	let gradient = if x1 == x0 { 0.0 } else { (y1 - y0) as f32 / (x1 - x0) as f32 };
+	let mut v = vec![];
+	let mut y = y0 as f32;
+	for x in x0..=x1 {
+		let base = y.floor();
+		let fract = y - base;
+		for (py, coverage) in [(base as isize, 1.0 - fract), (base as isize + 1, fract)].iter() {
+			if *coverage > 0.0 {
+				v.push(if steep { (*py, x, *coverage) } else { (x, *py, *coverage) });
+			}
+		}
+		y += gradient;
+	}
+	v
+}
+
+// Returns the positions covered by an ellipse around the middle of (cx, cy) with how much of each it covers.
+// Without antialiasing a pyxel is either covered or not, depending on its middle.
+pub fn ellipse(cx: isize, cy: isize, rx: f64, ry: f64, fill: bool, antialias: bool) -> Vec<(isize, isize, f32)> {
+	let samples = if antialias { 4 } else { 1 };
+	let (ox, oy) = (rx + 0.5, ry + 0.5);
+	let (ix, iy) = (rx - 0.5, ry - 0.5);
+	let inside = |x: f64, y: f64, a: f64, b: f64| -> bool { a > 0.0 && b > 0.0 && (x * x) / (a * a) + (y * y) / (b * b) <= 1.0 };
+
+	let mut v = vec![];
+	let (w, h) = (ox.ceil() as isize, oy.ceil() as isize);
+	for y in -h..=h {
+		for x in -w..=w {
+			let mut hits = 0;
+			for sy in 0..samples {
+				for sx in 0..samples {
+					// Offsets of the samples from the middle of the pyxel
+					let px = x as f64 + (sx as f64 + 0.5) / samples as f64 - 0.5;
+					let py = y as f64 + (sy as f64 + 0.5) / samples as f64 - 0.5;
+					if inside(px, py, ox, oy) && (fill || !inside(px, py, ix, iy)) {
+						hits += 1;
+					}
+				}
+			}
+			if hits > 0 {
+				v.push((cx + x, cy + y, hits as f32 / (samples * samples) as f32));
+			}
+		}
+	}
+	v
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn wu_lines_share_pyxels_between_rows() {
+		let points = wu_line(0, 0, 4, 2);
+		assert_eq!(points[..4], [(0, 0, 1.0), (1, 0, 0.5), (1, 1, 0.5), (2, 1, 1.0)]);
+		assert_eq!(points.last(), Some(&(4, 2, 1.0)));
+		assert_eq!(wu_line(0, 0, 0, 3), [(0, 0, 1.0), (0, 1, 1.0), (0, 2, 1.0), (0, 3, 1.0)]);
+	}
+
+	#[test]
+	fn antialiased_ellipses_cover_their_edges_partly() {
+		assert!(ellipse(0, 0, 4.0, 4.0, false, false).iter().all(|p| p.2 == 1.0));
+		let points = ellipse(0, 0, 4.0, 4.0, false, true);
+		assert!(points.iter().any(|p| p.2 > 0.0 && p.2 < 1.0));
+		assert!(points.iter().all(|p| p.2 > 0.0 && p.2 <= 1.0));
+	}
+}
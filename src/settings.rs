@@ -0,0 +1,95 @@
+use super::limits::*;
+use super::trace::*;
+use std::path::PathBuf;
+
+// Everything about a run that comes from outside the source
+#[derive(Debug, Clone)]
+pub struct Settings {
+	dir: PathBuf,
+	seed: u64,
+	time: f64,
+	infinite_x: bool,
+	infinite_y: bool,
+	padding: usize,
+	limits: Limits,
+	trace: Option<Trace>,
+}
+
+impl Settings {
+	// Returns new Settings. Takes no arguments.
+	pub fn new() -> Self {
+		Settings {
+			dir: PathBuf::new(),
+			seed: 0,
+			time: 0.0,
+			infinite_x: false,
+			infinite_y: false,
+			padding: 0,
+			limits: Limits::new(),
+			trace: None,
+		}
+	}
+
+	// Returns dir. The directory of the source file, which paths in the source are relative to.
+	pub fn dir(&self) -> &PathBuf {
+		&self.dir
+	}
+
+	// Returns seed. Where rand(), randint(a, b) and everything else random starts from.
+	pub fn seed(&self) -> u64 {
+		self.seed
+	}
+
+	// Returns time. What t is in shader expressions, for drawing frames of an animation.
+	pub fn time(&self) -> f64 {
+		self.time
+	}
+
+	// Returns whether the canvas grows to fit what is drawn across and down
+	pub fn infinite(&self) -> (bool, bool) {
+		(self.infinite_x, self.infinite_y)
+	}
+
+	// Returns padding. How much space is left around a grown canvas.
+	pub fn padding(&self) -> usize {
+		self.padding
+	}
+
+	pub fn limits(&self) -> &Limits {
+		&self.limits
+	}
+
+	// Returns trace. Which commands are logged as they run, if any.
+	pub fn trace(&self) -> Option<&Trace> {
+		self.trace.as_ref()
+	}
+
+	pub fn set_dir(&mut self, dir: PathBuf) {
+		self.dir = dir;
+	}
+
+	pub fn set_seed(&mut self, seed: u64) {
+		self.seed = seed;
+	}
+
+	pub fn set_time(&mut self, time: f64) {
+		self.time = time;
+	}
+
+	pub fn set_infinite(&mut self, x: bool, y: bool) {
+		self.infinite_x = x;
+		self.infinite_y = y;
+	}
+
+	pub fn set_padding(&mut self, padding: usize) {
+		self.padding = padding;
+	}
+
+	pub fn set_limits(&mut self, limits: Limits) {
+		self.limits = limits;
+	}
+
+	pub fn set_trace(&mut self, trace: Option<Trace>) {
+		self.trace = trace;
+	}
+}
@@ -0,0 +1,104 @@
+use super::eval::*;
+use super::limits::*;
+use super::transform::region;
+use meval::Expr;
+
+// Expressions for the red, green, blue and alpha of every pyxel in a region
+#[derive(Debug, Clone)]
+pub struct Shader {
+	channels: Vec<Expr>,
+	region: Vec<f64>,
+}
+
+impl Shader {
+	// Builds a Shader from directive arguments: three or four expressions followed by an optional region=x:y:w:h.
+	// Prints the first expression that can't be read and returns None.
+	pub fn from_args(args: &[String], eval: &Eval) -> Option<Self> {
+		let mut channels = vec![];
+		let mut region = vec![];
+		for arg in args.iter() {
+			if let Some(value) = arg.strip_prefix("region=") {
+				region = eval.numbers("shader", value.split(':'))?;
+				continue;
+			}
+			match eval.expr(arg) {
+				Some(expr) => channels.push(expr),
+				None => {
+					println!("toimg: Invalid shader expression: {}", arg);
+					return None;
+				}
+			}
+		}
+		if channels.len() < 3 || channels.len() > 4 {
+			return None;
+		}
+		Some(Shader { channels, region })
+	}
+
+	// Returns every pyxel of the region as (x, y, color) on a canvas of the given size.
+	// The expressions may use x and y, u and v (0 to 1 across the region), w and h (the size of the region) and t (the time).
+	// Each gives a value from 0 to 1. Every row is charged to the budget before it is worked out.
+	pub fn pyxels(
+		&self,
+		eval: &Eval,
+		width: usize,
+		height: usize,
+		time: f64,
+		budget: &mut Budget,
+	) -> Result<Vec<(isize, isize, [u8; 4])>, LimitError> {
+		let (rx, ry, rw, rh) = region(&self.region, width, height);
+		let mut v = vec![];
+		for y in ry..ry + rh {
+			budget.charge(rw)?;
+			for x in rx..rx + rw {
+				let vars = [
+					("x", x as f64),
+					("y", y as f64),
+					("u", ((x - rx) as f64 + 0.5) / rw as f64),
+					("v", ((y - ry) as f64 + 0.5) / rh as f64),
+					("w", rw as f64),
+					("h", rh as f64),
+					("t", time),
+				];
+				let mut color = [255; 4];
+				for (c, expr) in color.iter_mut().zip(self.channels.iter()) {
+					let n = eval.eval_expr(expr, &vars).unwrap_or(0.0);
+					*c = (n.clamp(0.0, 1.0) * 255.0).round() as u8;
+				}
+				v.push((x as isize, y as isize, color));
+			}
+		}
+		Ok(v)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn shade(args: &[&str], width: usize, height: usize, time: f64) -> Vec<(isize, isize, [u8; 4])> {
+		let eval = Eval::new(0);
+		let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+		let shader = Shader::from_args(&args, &eval).unwrap();
+		shader.pyxels(&eval, width, height, time, &mut Budget::new(Limits::new())).unwrap()
+	}
+
+	#[test]
+	fn paints_every_pyxel() {
+		let pyxels = shade(&["1", "x/2", "0"], 3, 1, 0.0);
+		assert_eq!(pyxels, [(0, 0, [255, 0, 0, 255]), (1, 0, [255, 128, 0, 255]), (2, 0, [255, 255, 0, 255])]);
+	}
+
+	#[test]
+	fn uv_cross_the_region() {
+		let pyxels = shade(&["u", "v", "t", "0.5", "region=2:2:2:1"], 8, 8, 2.0);
+		assert_eq!(pyxels, [(2, 2, [64, 128, 255, 128]), (3, 2, [191, 128, 255, 128])]);
+	}
+
+	#[test]
+	fn needs_three_or_four_expressions() {
+		let eval = Eval::new(0);
+		assert!(Shader::from_args(&["1".to_string(), "0".to_string()], &eval).is_none());
+		assert!(Shader::from_args(&["1".to_string(), "0".to_string(), "(".to_string()], &eval).is_none());
+	}
+}
@@ -0,0 +1,146 @@
+use super::eval::*;
+use super::parser::parse_color;
+use image::RgbaImage;
+use std::path::{Component, Path, PathBuf};
+
+// Returns the file a stamp names, relative to the source's directory,
+// or None if the name is absolute or climbs out of it with ..
+pub fn stamp_path(dir: &Path, name: &str) -> Option<PathBuf> {
+	let name = Path::new(name);
+	if name.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+		Some(dir.join(name))
+	} else {
+		None
+	}
+}
+
+// How an image loaded from a file is stamped at the pointer
+#[derive(Debug, Clone)]
+pub struct Stamp {
+	scale: f64,
+	crop: Option<(u32, u32, u32, u32)>,
+	key: Option<[u8; 4]>,
+}
+
+impl Stamp {
+	// Returns a new Stamp of the whole image at its own size
+	pub fn new() -> Self {
+		Stamp {
+			scale: 1.0,
+			crop: None,
+			key: None,
+		}
+	}
+
+	// Builds a Stamp from directive options: scale=n, crop=x:y:w:h and key=color
+	pub fn from_args(args: &[String], eval: &Eval) -> Self {
+		let mut stamp = Stamp::new();
+		for arg in args.iter() {
+			let (key, value) = match arg.find('=') {
+				Some(i) => (arg[..i].trim(), arg[i + 1..].trim()),
+				None => continue,
+			};
+			match key {
+				"scale" => {
+					if let Some(n) = eval.eval(value) {
+						stamp.set_scale(n);
+					}
+				}
+				"crop" => {
+					let nums: Vec<u32> = match eval.numbers("stamp", value.split(':')) {
+						Some(nums) => nums.iter().map(|n| n.max(0.0) as u32).collect(),
+						None => continue,
+					};
+					if nums.len() == 4 {
+						stamp.set_crop(nums[0], nums[1], nums[2], nums[3]);
+					}
+				}
+				"key" => {
+					if let Some(color) = parse_color(value) {
+						stamp.set_key(color);
+					}
+				}
+				_ => (),
+			}
+		}
+		stamp
+	}
+
+	// Set respective values
+	pub fn set_scale(&mut self, scale: f64) {
+		if scale > 0.0 {
+			self.scale = scale;
+		}
+	}
+
+	pub fn set_crop(&mut self, x: u32, y: u32, w: u32, h: u32) {
+		self.crop = Some((x, y, w, h));
+	}
+
+	// Pixels of this color are left out
+	pub fn set_key(&mut self, key: [u8; 4]) {
+		self.key = Some(key);
+	}
+
+	// Returns the part of the image stamped as x, y, width and height
+	fn crop(&self, image: &RgbaImage) -> (u32, u32, u32, u32) {
+		let (iw, ih) = image.dimensions();
+		match self.crop {
+			Some((x, y, w, h)) => {
+				let x = x.min(iw);
+				let y = y.min(ih);
+				(x, y, w.min(iw - x), h.min(ih - y))
+			}
+			None => (0, 0, iw, ih),
+		}
+	}
+
+	// Returns the width and height of the stamp once scaled
+	pub fn size(&self, image: &RgbaImage) -> (u32, u32) {
+		let (_, _, cw, ch) = self.crop(image);
+		((cw as f64 * self.scale).round() as u32, (ch as f64 * self.scale).round() as u32)
+	}
+
+	// Returns every pyxel of the stamp as (x, y, color), relative to its top left corner.
+	// Fully transparent pixels are left out.
+	pub fn pyxels(&self, image: &RgbaImage) -> Vec<(isize, isize, [u8; 4])> {
+		let (cx, cy, cw, ch) = self.crop(image);
+		let (w, h) = self.size(image);
+
+		let mut v = vec![];
+		for y in 0..h {
+			for x in 0..w {
+				// Nearest neighbour, so pyxel art stays sharp
+				let sx = cx + ((x as f64 / self.scale) as u32).min(cw - 1);
+				let sy = cy + ((y as f64 / self.scale) as u32).min(ch - 1);
+				let p = image.get_pixel(sx, sy);
+				let color = [p[0], p[1], p[2], p[3]];
+				if color[3] == 0 {
+					continue;
+				}
+				if let Some(key) = self.key {
+					if key[..3] == color[..3] {
+						continue;
+					}
+				}
+				v.push((x as isize, y as isize, color));
+			}
+		}
+		v
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn stamp_paths_stay_in_the_directory() {
+		let dir = Path::new("art");
+		assert_eq!(stamp_path(dir, "a.png"), Some(dir.join("a.png")));
+		assert_eq!(stamp_path(dir, "./sprites/a.png"), Some(dir.join("./sprites/a.png")));
+		assert_eq!(stamp_path(dir, "../a.png"), None);
+		assert_eq!(stamp_path(dir, "sprites/../../a.png"), None);
+		assert_eq!(stamp_path(dir, "/etc/a.png"), None);
+	}
+}
@@ -0,0 +1,71 @@
+use std::f64::consts::PI;
+
+// How every put is mirrored across the canvas
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Symmetry {
+	None,
+	Vertical(f64),
+	Horizontal(f64),
+	Both(f64, f64),
+	Radial(usize, f64, f64),
+}
+
+impl Symmetry {
+	// Builds a Symmetry from directive arguments, e.g. "vertical", "16".
+	// Axes are given as edge coordinates and default to the middle of the canvas.
+	pub fn from_args(mode: &str, args: &[f64], width: usize, height: usize) -> Symmetry {
+		let cx = width as f64 / 2.0;
+		let cy = height as f64 / 2.0;
+		let arg = |i: usize, default: f64| -> f64 {
+			match args.get(i) {
+				Some(n) => *n,
+				None => default,
+			}
+		};
+		match mode {
+			"vertical" | "v" => Symmetry::Vertical(arg(0, cx)),
+			"horizontal" | "h" => Symmetry::Horizontal(arg(0, cy)),
+			"both" => Symmetry::Both(arg(0, cx), arg(1, cy)),
+			"radial" => {
+				let n = arg(0, 4.0).max(1.0) as usize;
+				Symmetry::Radial(n, arg(1, cx), arg(2, cy))
+			}
+			_ => Symmetry::None,
+		}
+	}
+
+	// Returns every position a put at (x, y) lands on, including (x, y) itself
+	pub fn points(&self, x: isize, y: isize) -> Vec<(isize, isize)> {
+		let mirror = |n: isize, axis: f64| -> isize { (2.0 * axis - 1.0 - n as f64).round() as isize };
+		let mut points = vec![(x, y)];
+		match *self {
+			Symmetry::None => (),
+			Symmetry::Vertical(ax) => {
+				points.push((mirror(x, ax), y));
+			}
+			Symmetry::Horizontal(ay) => {
+				points.push((x, mirror(y, ay)));
+			}
+			Symmetry::Both(ax, ay) => {
+				points.push((mirror(x, ax), y));
+				points.push((x, mirror(y, ay)));
+				points.push((mirror(x, ax), mirror(y, ay)));
+			}
+			Symmetry::Radial(n, cx, cy) => {
+				// Rotate the pixel's centre around (cx, cy)
+				let dx = x as f64 + 0.5 - cx;
+				let dy = y as f64 + 0.5 - cy;
+				for k in 1..n {
+					let angle = 2.0 * PI * k as f64 / n as f64;
+					let (sin, cos) = angle.sin_cos();
+					let rx = cx + dx * cos - dy * sin;
+					let ry = cy + dx * sin + dy * cos;
+					points.push((rx.floor() as isize, ry.floor() as isize));
+				}
+			}
+		}
+		points.sort();
+		points.dedup();
+		points
+	}
+}
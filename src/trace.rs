@@ -0,0 +1,112 @@
+use super::cmd::*;
+use super::pointer::*;
+
+// Which commands --trace logs to stderr. With no filters it logs every one.
+#[derive(Debug, Clone)]
+pub struct Trace {
+	line: Option<usize>,
+	command: Option<Vec<usize>>,
+}
+
+impl Trace {
+	// Returns a new Trace that logs everything
+	pub fn new() -> Self {
+		Trace {
+			line: None,
+			command: None,
+		}
+	}
+
+	// Only logs commands written on this line of the source
+	pub fn set_line(&mut self, line: usize) {
+		self.line = Some(line);
+	}
+
+	// Only logs the command at this index, and what it expands to
+	pub fn set_command(&mut self, command: Vec<usize>) {
+		self.command = Some(command);
+	}
+
+	// Returns whether the command at index i is logged. Path is the indices of the directives it was expanded from.
+	pub fn matches(&self, path: &[usize], i: usize, cmd: &Command) -> bool {
+		let span = cmd.span();
+		let at = path.iter().chain(std::iter::once(&i));
+		self.line.map_or(true, |l| span.line() <= l && l <= span.end_line())
+			&& self.command.as_ref().map_or(true, |n| n.len() <= path.len() + 1 && n.iter().zip(at).all(|(a, b)| a == b))
+	}
+
+	// Logs a command that ran: where it was written, where the pointer was before and after,
+	// the pointer's bounds and which ways it is reversed, and every loop it is inside as index:times left
+	pub fn log(&self, path: &[usize], i: usize, cmd: &Command, before: &Pointer, after: &Pointer, loops: &[(usize, usize)]) {
+		let text = if cmd.text().is_empty() {
+			cmd.verb().to_string()
+		} else {
+			cmd.text().replace('\n', " ")
+		};
+		let reverse = format!(
+			"{}{}",
+			if after.reverse_move_x() { 'X' } else { '-' },
+			if after.reverse_move_y() { 'Y' } else { '-' },
+		);
+		let loops = if loops.is_empty() {
+			"-".to_string()
+		} else {
+			loops.iter().map(|(i, n)| format!("#{}:{}", index(path, *i), n)).collect::<Vec<String>>().join(" ")
+		};
+		eprintln!(
+			"#{} {} {}  {} ({}, {}) -> ({}, {})  bounds ({}, {})-({}, {})  reverse {}  loops {}",
+			index(path, i),
+			cmd.span(),
+			text,
+			after.name(),
+			before.x(),
+			before.y(),
+			after.x(),
+			after.y(),
+			after.left(),
+			after.top(),
+			after.right(),
+			after.bottom(),
+			reverse,
+			loops,
+		);
+	}
+}
+
+// Returns the index of the i-th command of a list as written in the log, after the indices of the directives
+// it was expanded from, e.g. 5.2 for the third command a directive at index 5 expanded to
+fn index(path: &[usize], i: usize) -> String {
+	path.iter().chain(std::iter::once(&i)).map(|n| n.to_string()).collect::<Vec<String>>().join(".")
+}
+
+// Reads an index written like those in the log, e.g. 5 or 5.2
+pub fn parse_index(s: &str) -> Option<Vec<usize>> {
+	s.split('.').map(|n| n.parse::<usize>().ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn nested_indices() {
+		assert_eq!(index(&[], 4), "4");
+		assert_eq!(index(&[5, 0], 2), "5.0.2");
+		assert_eq!(parse_index("5.2"), Some(vec![5, 2]));
+		assert_eq!(parse_index("5."), None);
+	}
+
+	#[test]
+	fn matches_what_a_directive_expands_to() {
+		let cmd = Command::new('r', [255, 0, 0, 255], 1, 1, false);
+		let mut trace = Trace::new();
+		trace.set_command(vec![5]);
+		assert!(trace.matches(&[], 5, &cmd));
+		assert!(trace.matches(&[5], 2, &cmd));
+		assert!(!trace.matches(&[], 2, &cmd));
+		trace.set_command(vec![5, 2]);
+		assert!(!trace.matches(&[], 5, &cmd));
+		assert!(trace.matches(&[5], 2, &cmd));
+		assert!(!trace.matches(&[5], 3, &cmd));
+	}
+}
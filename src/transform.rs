@@ -0,0 +1,147 @@
+use super::pyxel::*;
+
+// A change applied to what has already been drawn inside a region
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Transform {
+	FlipHorizontal,
+	FlipVertical,
+	Rotate(usize),
+	Shift(isize, isize),
+	Scroll(isize, isize),
+}
+
+impl Transform {
+	// Applies the transform to the region given by the arguments x, y, w and h
+	pub fn apply(&self, pyxels: &mut PyxelVec, nums: &[f64]) {
+		let (x, y, w, h) = transform_area(nums, pyxels);
+		if w == 0 || h == 0 {
+			return;
+		}
+		let (wi, hi) = (w as isize, h as isize);
+		match *self {
+			Transform::FlipHorizontal => {
+				pyxels.transform(x, y, w, h, |sx, sy| Some((x + wi - 1 - sx as isize, y + sy as isize)));
+			}
+			Transform::FlipVertical => {
+				pyxels.transform(x, y, w, h, |sx, sy| Some((x + sx as isize, y + hi - 1 - sy as isize)));
+			}
+			// Clockwise, keeping the top left corner of the region where it is.
+			// The region keeps its shape, so if it isn't square what turns out of it is dropped.
+			Transform::Rotate(degrees) => {
+				let inside = |nx: isize, ny: isize| if nx < x + wi && ny < y + hi { Some((nx, ny)) } else { None };
+				match degrees % 360 {
+					90 => pyxels.transform(x, y, w, h, |sx, sy| inside(x + hi - 1 - sy as isize, y + sx as isize)),
+					180 => pyxels.transform(x, y, w, h, |sx, sy| {
+						Some((x + wi - 1 - sx as isize, y + hi - 1 - sy as isize))
+					}),
+					270 => pyxels.transform(x, y, w, h, |sx, sy| inside(x + sy as isize, y + wi - 1 - sx as isize)),
+					_ => (),
+				}
+			}
+			Transform::Shift(dx, dy) => {
+				pyxels.transform(x, y, w, h, |sx, sy| {
+					let nx = sx as isize + dx;
+					let ny = sy as isize + dy;
+					if nx >= 0 && ny >= 0 && nx < wi && ny < hi {
+						Some((x + nx, y + ny))
+					} else {
+						None
+					}
+				});
+			}
+			Transform::Scroll(dx, dy) => {
+				pyxels.transform(x, y, w, h, |sx, sy| {
+					Some((x + (sx as isize + dx).rem_euclid(wi), y + (sy as isize + dy).rem_euclid(hi)))
+				});
+			}
+		}
+	}
+}
+
+// Returns the region a transform works on. Without x, y, w and h it is the whole output,
+// which along infinite axes is what has been painted plus the padding.
+pub fn transform_area(nums: &[f64], pyxels: &PyxelVec) -> (isize, isize, usize, usize) {
+	if nums.len() < 4 {
+		return pyxels.area();
+	}
+	let (x, y, w, h) = region(nums, pyxels.width(), pyxels.height());
+	(x as isize, y as isize, w, h)
+}
+
+// Returns the region given by the arguments x, y, w and h, clipped to the canvas.
+// Without all four the region is the whole canvas.
+pub fn region(nums: &[f64], width: usize, height: usize) -> (usize, usize, usize, usize) {
+	if nums.len() < 4 {
+		return (0, 0, width, height);
+	}
+	let x = (nums[0].max(0.0) as usize).min(width);
+	let y = (nums[1].max(0.0) as usize).min(height);
+	let w = (nums[2].max(0.0) as usize).min(width - x);
+	let h = (nums[3].max(0.0) as usize).min(height - y);
+	(x, y, w, h)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Returns which pyxels of the area are painted, a row per string
+	fn painted(pyxels: &PyxelVec) -> Vec<String> {
+		let (x, y, w, h) = pyxels.area();
+		let flat = pyxels.flatten_region(x, y, w, h);
+		flat.chunks(w).map(|row| row.iter().map(|c| if c[3] > 0.0 { '#' } else { '.' }).collect()).collect()
+	}
+
+	fn canvas(width: usize, height: usize, points: &[(isize, isize)]) -> PyxelVec {
+		let mut pyxels = PyxelVec::new(width, height);
+		for &(x, y) in points {
+			pyxels.push(Pyxel::new([255, 0, 0, 255], x, y));
+		}
+		pyxels
+	}
+
+	#[test]
+	fn flips() {
+		let mut pyxels = canvas(3, 2, &[(0, 0), (1, 1)]);
+		Transform::FlipHorizontal.apply(&mut pyxels, &[]);
+		assert_eq!(painted(&pyxels), ["..#", ".#."]);
+		Transform::FlipVertical.apply(&mut pyxels, &[]);
+		assert_eq!(painted(&pyxels), [".#.", "..#"]);
+	}
+
+	#[test]
+	fn rotates_clockwise() {
+		let mut pyxels = canvas(3, 3, &[(0, 0), (1, 0)]);
+		Transform::Rotate(90).apply(&mut pyxels, &[]);
+		assert_eq!(painted(&pyxels), ["..#", "..#", "..."]);
+		Transform::Rotate(180).apply(&mut pyxels, &[]);
+		assert_eq!(painted(&pyxels), ["...", "#..", "#.."]);
+	}
+
+	#[test]
+	fn rotating_stays_inside_the_region() {
+		let mut pyxels = canvas(4, 4, &[(0, 0), (3, 0), (0, 3)]);
+		Transform::Rotate(90).apply(&mut pyxels, &[0.0, 0.0, 3.0, 1.0]);
+		assert_eq!(painted(&pyxels), ["#..#", "....", "....", "#..."]);
+	}
+
+	#[test]
+	fn infinite_canvases_transform_what_was_painted() {
+		let mut pyxels = canvas(2, 2, &[(-3, 5), (-2, 5)]);
+		pyxels.set_infinite(true, true);
+		Transform::FlipVertical.apply(&mut pyxels, &[]);
+		assert_eq!(pyxels.area(), (-3, 5, 2, 1));
+		Transform::Rotate(270).apply(&mut pyxels, &[]);
+		assert_eq!(painted(&pyxels), ["#"]);
+	}
+
+	#[test]
+	fn shifts_and_scrolls() {
+		let mut pyxels = canvas(3, 1, &[(0, 0), (2, 0)]);
+		Transform::Shift(1, 0).apply(&mut pyxels, &[]);
+		assert_eq!(painted(&pyxels), [".#."]);
+		let mut pyxels = canvas(3, 1, &[(0, 0), (2, 0)]);
+		Transform::Scroll(1, 0).apply(&mut pyxels, &[]);
+		assert_eq!(painted(&pyxels), ["##."]);
+	}
+}
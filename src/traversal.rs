@@ -0,0 +1,229 @@
+// The order consecutive puts follow inside the pointer's bounds
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Traversal {
+	Row,
+	Serpentine,
+	Column,
+	Spiral,
+	Hilbert,
+}
+
+impl Traversal {
+	// Returns the Traversal called name, if there is one
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"row" | "rows" => Some(Traversal::Row),
+			"serpentine" | "boustrophedon" => Some(Traversal::Serpentine),
+			"column" | "columns" => Some(Traversal::Column),
+			"spiral" => Some(Traversal::Spiral),
+			"hilbert" => Some(Traversal::Hilbert),
+			_ => None,
+		}
+	}
+
+	// Returns the cell after (x, y) in the bounds from (left, top) to (right, bottom), not included.
+	// After the last cell comes the first again. Rows are left to the pointer's own moves and aren't handled here.
+	pub fn next(&self, x: isize, y: isize, left: isize, top: isize, right: isize, bottom: isize) -> (isize, isize) {
+		let (w, h) = (right - left, bottom - top);
+		if w <= 0 || h <= 0 {
+			return (x, y);
+		}
+		let x = (x - left).max(0).min(w - 1);
+		let y = (y - top).max(0).min(h - 1);
+		let (nx, ny) = match self {
+			Traversal::Row => (x, y),
+			Traversal::Serpentine => {
+				let nx = if y % 2 == 0 { x + 1 } else { x - 1 };
+				if nx >= 0 && nx < w {
+					(nx, y)
+				} else if y + 1 < h {
+					(x, y + 1)
+				} else {
+					(0, 0)
+				}
+			}
+			Traversal::Column => {
+				if y + 1 < h {
+					(x, y + 1)
+				} else if x + 1 < w {
+					(x + 1, 0)
+				} else {
+					(0, 0)
+				}
+			}
+			Traversal::Spiral => spiral_cell((spiral_index(x, y, w, h) + 1) % (w * h), w, h),
+			Traversal::Hilbert => {
+				let mut n = 1;
+				while n < w.max(h) {
+					n *= 2;
+				}
+				// The curve fills a square, so skip the part of it outside the bounds.
+				// It goes through each s by s square of it in one go, so whole squares outside are skipped at once.
+				let mut d = hilbert_index(x, y, n);
+				loop {
+					d = (d + 1) % (n * n);
+					let (hx, hy) = hilbert_cell(d, n);
+					if hx < w && hy < h {
+						break (hx, hy);
+					}
+					let mut s = 1;
+					while s < n && d % (4 * s * s) == 0 && (hx & !(2 * s - 1) >= w || hy & !(2 * s - 1) >= h) {
+						s *= 2;
+					}
+					d += s * s - 1;
+				}
+			}
+		};
+		(left + nx, top + ny)
+	}
+}
+
+// Returns the size of a ring k cells in from the edges of a w by h spiral
+fn ring(k: isize, w: isize, h: isize) -> (isize, isize) {
+	(w - 2 * k, h - 2 * k)
+}
+
+// Returns where (x, y) comes in a spiral inward and clockwise from the top left of a w by h rectangle
+fn spiral_index(x: isize, y: isize, w: isize, h: isize) -> isize {
+	let k = x.min(y).min(w - 1 - x).min(h - 1 - y);
+	let (rw, rh) = ring(k, w, h);
+	let (a, b) = (x - k, y - k);
+	let pos = if b == 0 {
+		a
+	} else if a == rw - 1 {
+		rw - 1 + b
+	} else if b == rh - 1 {
+		2 * (rw - 1) + rh - 1 - a
+	} else {
+		2 * (rw - 1) + 2 * (rh - 1) - b
+	};
+	w * h - rw * rh + pos
+}
+
+// Returns the cell that comes i-th in the spiral
+fn spiral_cell(i: isize, w: isize, h: isize) -> (isize, isize) {
+	let mut k = 0;
+	loop {
+		let (nw, nh) = ring(k + 1, w, h);
+		if nw <= 0 || nh <= 0 || w * h - nw * nh > i {
+			break;
+		}
+		k += 1;
+	}
+	let (rw, rh) = ring(k, w, h);
+	let pos = i - (w * h - rw * rh);
+	let (a, b) = if pos < rw {
+		(pos, 0)
+	} else if pos < rw + rh - 1 {
+		(rw - 1, pos - (rw - 1))
+	} else if pos < 2 * (rw - 1) + rh {
+		(2 * (rw - 1) + rh - 1 - pos, rh - 1)
+	} else {
+		(0, 2 * (rw - 1) + 2 * (rh - 1) - pos)
+	};
+	(a + k, b + k)
+}
+
+// Returns where (x, y) comes along a Hilbert curve filling an n by n square, n being a power of two
+fn hilbert_index(x: isize, y: isize, n: isize) -> isize {
+	let (mut x, mut y) = (x, y);
+	let mut d = 0;
+	let mut s = n / 2;
+	while s > 0 {
+		let rx = ((x & s) > 0) as isize;
+		let ry = ((y & s) > 0) as isize;
+		d += s * s * ((3 * rx) ^ ry);
+		hilbert_rotate(n, &mut x, &mut y, rx, ry);
+		s /= 2;
+	}
+	d
+}
+
+// Returns the cell that comes d-th along the Hilbert curve
+fn hilbert_cell(d: isize, n: isize) -> (isize, isize) {
+	let (mut x, mut y) = (0, 0);
+	let mut t = d;
+	let mut s = 1;
+	while s < n {
+		let rx = 1 & (t / 2);
+		let ry = 1 & (t ^ rx);
+		hilbert_rotate(s, &mut x, &mut y, rx, ry);
+		x += s * rx;
+		y += s * ry;
+		t /= 4;
+		s *= 2;
+	}
+	(x, y)
+}
+
+// Turns a quadrant of the curve so its pieces join up
+fn hilbert_rotate(n: isize, x: &mut isize, y: &mut isize, rx: isize, ry: isize) {
+	if ry == 0 {
+		if rx == 1 {
+			*x = n - 1 - *x;
+			*y = n - 1 - *y;
+		}
+		std::mem::swap(x, y);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Returns the cells visited from (left, top) until the traversal comes back round
+	fn walk(traversal: Traversal, left: isize, top: isize, w: isize, h: isize) -> Vec<(isize, isize)> {
+		let mut cells = vec![(left, top)];
+		loop {
+			let (x, y) = *cells.last().unwrap();
+			let next = traversal.next(x, y, left, top, left + w, top + h);
+			if next == (left, top) || cells.len() > (w * h) as usize {
+				return cells;
+			}
+			cells.push(next);
+		}
+	}
+
+	#[test]
+	fn spiral_goes_clockwise_inward() {
+		let cells = walk(Traversal::Spiral, 0, 0, 3, 3);
+		assert_eq!(cells, vec![(0, 0), (1, 0), (2, 0), (2, 1), (2, 2), (1, 2), (0, 2), (0, 1), (1, 1)]);
+		let cells = walk(Traversal::Spiral, 2, 5, 4, 2);
+		assert_eq!(cells, vec![(2, 5), (3, 5), (4, 5), (5, 5), (5, 6), (4, 6), (3, 6), (2, 6)]);
+	}
+
+	#[test]
+	fn hilbert_visits_neighbours() {
+		assert_eq!(walk(Traversal::Hilbert, 0, 0, 2, 2), vec![(0, 0), (0, 1), (1, 1), (1, 0)]);
+		let cells = walk(Traversal::Hilbert, 0, 0, 8, 8);
+		assert_eq!(cells.len(), 64);
+		for pair in cells.windows(2) {
+			let (a, b) = (pair[0], pair[1]);
+			assert_eq!((a.0 - b.0).abs() + (a.1 - b.1).abs(), 1, "{:?} to {:?}", a, b);
+		}
+	}
+
+	#[test]
+	fn hilbert_skips_far_outside() {
+		// The curve's square is 2^20 across, almost all of it outside
+		let mut cells = vec![(0, 0)];
+		for _ in 0..8 {
+			let (x, y) = *cells.last().unwrap();
+			cells.push(Traversal::Hilbert.next(x, y, 0, 0, 1, 1 << 20));
+		}
+		assert_eq!(cells, (0..9).map(|y| (0, y)).collect::<Vec<(isize, isize)>>());
+	}
+
+	#[test]
+	fn every_cell_once() {
+		for &traversal in &[Traversal::Serpentine, Traversal::Column, Traversal::Spiral, Traversal::Hilbert] {
+			for &(w, h) in &[(1, 1), (1, 5), (5, 1), (3, 4), (5, 3), (6, 6)] {
+				let mut cells = walk(traversal, -1, 2, w, h);
+				assert_eq!(cells.len(), (w * h) as usize, "{:?} {}x{}", traversal, w, h);
+				cells.sort();
+				cells.dedup();
+				assert_eq!(cells.len(), (w * h) as usize, "{:?} {}x{}", traversal, w, h);
+			}
+		}
+	}
+}